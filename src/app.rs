@@ -1,10 +1,21 @@
-use std::cmp::min;
+use std::{cmp::min, path::Path, sync::Arc};
 
 use crate::{
-    bluetooth::{BluetoothEvent, mitch::MitchList},
+    bluetooth::{
+        BluetoothEvent,
+        actor::MitchCommand,
+        adapter::{AdapterChoice, AdapterInfo},
+        firmware::FirmwareImage,
+        group,
+        mitch::MitchList,
+        recorder::RECORDING_DIR,
+        registry::{Registry, Role},
+    },
+    errors::ErrorLog,
     event::{AppEvent, Event, EventHandler},
+    markers::MarkerOutlet,
 };
-use color_eyre::eyre::eyre;
+use color_eyre::eyre::{OptionExt, eyre};
 use crossterm::event::KeyEventKind;
 use ratatui::{
     DefaultTerminal,
@@ -21,35 +32,78 @@ pub struct App {
     pub mitches: MitchList,
     /// Event handler.
     pub events: EventHandler,
+    /// Firmware image given on the command line, installed with `U`.
+    pub firmware: Option<Arc<FirmwareImage>>,
+    /// Whether `firmware` may replace newer firmware.
+    pub allow_downgrade: bool,
+    /// Errors that did not end the app.
+    pub errors: ErrorLog,
+    /// The adapters of the system as last reported by the discovery, `None` until its first
+    /// report.
+    pub adapters: Option<Vec<AdapterInfo>>,
+    /// Entry highlighted in the adapter list, where 0 stands for all adapters.
+    pub selected_adapter: usize,
+    /// Marker stream, opened with the first group start or stop.
+    pub markers: Option<Arc<MarkerOutlet>>,
 }
 
 #[derive(Debug)]
 pub enum AppState {
     Menu,
     Mitch,
+    /// The error log.
+    Errors,
+    /// The list of adapters to scan with.
+    Adapters,
 }
 
 impl Default for App {
     fn default() -> Self {
+        Self::with_events(EventHandler::default())
+    }
+}
+
+impl App {
+    /// Constructs a new instance of [`App`] scanning with the chosen `adapter`, setting up known
+    /// devices as `registry` says.
+    pub fn new(adapter: AdapterChoice, registry: Registry) -> Self {
+        Self::with_events(EventHandler::new(adapter, registry))
+    }
+
+    /// Constructs a new instance of [`App`] controlling `count` simulated mitches.
+    pub fn simulated(count: usize) -> Self {
+        Self::with_events(EventHandler::simulated(count))
+    }
+
+    fn with_events(events: EventHandler) -> Self {
         Self {
             running: true,
-            events: EventHandler::new(),
+            events,
             mitches: MitchList::new(),
             state: AppState::Menu,
+            firmware: None,
+            allow_downgrade: false,
+            errors: ErrorLog::new(),
+            adapters: None,
+            selected_adapter: 0,
+            markers: None,
         }
     }
-}
-
-impl App {
-    /// Constructs a new instance of [`App`].
-    pub fn new() -> Self {
-        Self::default()
-    }
 
     /// Run the application's main loop.
     pub async fn run(mut self, mut terminal: DefaultTerminal) -> color_eyre::Result<()> {
+        let result = self.main_loop(&mut terminal).await;
+        // No stream may push into the file once the footers complete it, also when the loop
+        // failed
+        self.mitches.close().await;
+        let stopped = self.mitches.xdf.stop();
+        result.and(stopped)
+    }
+
+    /// Draws and handles events until the app quits or drawing or reading events fails.
+    async fn main_loop(&mut self, terminal: &mut DefaultTerminal) -> color_eyre::Result<()> {
         while self.running {
-            terminal.draw(|frame| frame.render_widget(&self, frame.area()))?;
+            terminal.draw(|frame| frame.render_widget(&*self, frame.area()))?;
             match self.events.next().await? {
                 Event::Tick => {
                     self.tick().await?;
@@ -59,29 +113,43 @@ impl App {
                         self.handle_key_events(key_event)?
                     }
                 }
-                Event::App(app_event) => match app_event {
-                    AppEvent::Quit => self.quit(),
-                    AppEvent::PrevMitch => self.prev(),
-                    AppEvent::NextMitch => self.next(),
-                    AppEvent::Connect => {
-                        self.mitches.get_active_mut().connect().await?;
+                Event::App(app_event) => {
+                    // A failed request is shown, it must not end the app
+                    if let Err(e) = self.handle_app_event(app_event) {
+                        self.errors.push(None, e);
+                    }
+                }
+                Event::Bluetooth(bluetooth_event) => match bluetooth_event {
+                    BluetoothEvent::Discovered(mitch) => {
+                        self.mitches.insert(*mitch, self.events.sender());
                     }
-                    AppEvent::Disconnect => {
-                        self.mitches.get_active_mut().disconnect().await?;
+                    BluetoothEvent::Updated {
+                        id,
+                        name,
+                        rssi,
+                        tx_power,
+                    } => {
+                        let advertised = MitchCommand::Advertised {
+                            name,
+                            rssi,
+                            tx_power,
+                        };
+                        self.mitches.send_to(&id, advertised);
                     }
-                    AppEvent::StopRecord => {
-                        self.mitches.get_active_mut().stop_recording().await?;
+                    BluetoothEvent::Reachable(transport) => self.mitches.add_route(transport),
+                    BluetoothEvent::Connected { id, adapter } => {
+                        self.mitches.send_to(&id, MitchCommand::LinkUp(adapter))
                     }
-                    AppEvent::StartRecord => {
-                        self.mitches.get_active_mut().start_recording().await?;
+                    BluetoothEvent::Disconnected { id, adapter } => {
+                        self.mitches.send_to(&id, MitchCommand::LinkDown(adapter))
                     }
-                },
-                Event::Bluetooth(bluetooth_event) => match bluetooth_event {
-                    BluetoothEvent::Discovered(mitch) => {
-                        self.mitches.insert(mitch);
+                    BluetoothEvent::Adapters(adapters) => {
+                        self.selected_adapter = min(self.selected_adapter, adapters.len());
+                        self.adapters = Some(adapters);
                     }
-                    BluetoothEvent::NotActive => {
-                        return Err(eyre!("Bluetooth not activated"));
+                    BluetoothEvent::Error { device, error } => self.errors.push(device, error),
+                    BluetoothEvent::Notice { device, message } => {
+                        self.errors.push_quiet(Some(device), message)
                     }
                 },
             }
@@ -89,6 +157,139 @@ impl App {
         Ok(())
     }
 
+    /// Handles the app events by passing them on to the active mitch.
+    fn handle_app_event(&mut self, app_event: AppEvent) -> color_eyre::Result<()> {
+        match app_event {
+            AppEvent::Quit => self.quit(),
+            AppEvent::PrevMitch => self.prev(),
+            AppEvent::NextMitch => self.next(),
+            // A pair connects, starts and stops together
+            AppEvent::Connect => self.send_paired(MitchCommand::Connect),
+            AppEvent::Disconnect => self.send_paired(MitchCommand::Disconnect),
+            AppEvent::StopRecord => match self.paired_ids()[..] {
+                [_, _] => self.stop_group(self.paired_ids())?,
+                _ => self.mitches.send_active(MitchCommand::StopRecording),
+            },
+            AppEvent::StartRecord => match self.paired_ids()[..] {
+                [_, _] => self.start_group(self.paired_ids())?,
+                _ => self.mitches.send_active(MitchCommand::StartRecording),
+            },
+            AppEvent::StartLog => self.mitches.send_active(MitchCommand::StartLogging),
+            AppEvent::StopLog => self.mitches.send_active(MitchCommand::StopLogging),
+            AppEvent::ListFiles => self.mitches.send_active(MitchCommand::ListFiles),
+            AppEvent::PrevFile => self.mitches.send_active(MitchCommand::PrevFile),
+            AppEvent::NextFile => self.mitches.send_active(MitchCommand::NextFile),
+            AppEvent::Readout => self.mitches.send_active(MitchCommand::Readout),
+            AppEvent::EraseMemory => self.mitches.send_active(MitchCommand::EraseMemory),
+            AppEvent::ToggleGapFill => self.mitches.send_active(MitchCommand::ToggleGapFill),
+            AppEvent::ToggleCsv => self.mitches.send_active(MitchCommand::ToggleCsv),
+            AppEvent::SyncClock => self.mitches.send_active(MitchCommand::SyncClock),
+            AppEvent::FirmwareUpdate => {
+                let image = self
+                    .firmware
+                    .clone()
+                    .ok_or_eyre("No firmware file given, start with --firmware <file>")?;
+                self.mitches.send_active(MitchCommand::UpdateFirmware {
+                    image,
+                    allow_downgrade: self.allow_downgrade,
+                });
+            }
+            AppEvent::NextMode => self.mitches.send_active(MitchCommand::NextMode),
+            AppEvent::NextFrequency => self.mitches.send_active(MitchCommand::NextFrequency),
+            AppEvent::SelectAdapter(choice) => {
+                self.events.select_adapter(choice);
+                self.adapters = None;
+            }
+            AppEvent::ToggleSelect => self.mitches.toggle_selected(),
+            AppEvent::GroupStart => self.start_group(self.selected_ids()?)?,
+            AppEvent::GroupStop => self.stop_group(self.selected_ids()?)?,
+            AppEvent::Pair => match self.mitches.active_with_partner()[..] {
+                [_, _] => self.mitches.unpair_active(),
+                _ => self.mitches.pair_selected()?,
+            },
+            AppEvent::SwapSides => self.mitches.swap_sides(),
+            AppEvent::ToggleCombined => self.mitches.toggle_combined(),
+            AppEvent::Remember => self.remember()?,
+            AppEvent::ToggleXdf => match self.mitches.xdf.path() {
+                Some(_) => self.mitches.xdf.stop()?,
+                None => {
+                    self.mitches.xdf.start(Path::new(RECORDING_DIR))?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Ids of the active mitch and its partner, if it is paired.
+    fn paired_ids(&self) -> Vec<String> {
+        let mitches = self.mitches.active_with_partner();
+        mitches.iter().map(|m| m.id().to_string()).collect()
+    }
+
+    fn send_paired(&self, command: MitchCommand) {
+        for mitch in self.mitches.active_with_partner() {
+            mitch.send(command.clone());
+        }
+    }
+
+    /// Keeps the settings of the active mitch and its partner in the registry, a pair as the
+    /// feet of its subject unless a role off the feet was given by hand.
+    fn remember(&self) -> color_eyre::Result<()> {
+        for mitch in self.mitches.active_with_partner() {
+            let mut entry = mitch.snapshot().entry;
+            if let Some(subject) = self.mitches.subject_of(mitch.id()) {
+                entry.subject = Some(subject.name.clone());
+                if entry.role.is_none_or(|r| r.side().is_some()) {
+                    entry.role = subject.side(mitch.id()).map(Role::foot);
+                }
+            }
+            self.events.registry().remember(mitch.id(), entry.clone())?;
+            mitch.send(MitchCommand::Register(entry));
+        }
+        Ok(())
+    }
+
+    /// Ids of the selected mitches and the partners of the paired ones.
+    fn selected_ids(&self) -> color_eyre::Result<Vec<String>> {
+        let ids: Vec<String> = self
+            .mitches
+            .selected_with_partners()
+            .iter()
+            .map(|m| m.id().to_string())
+            .collect();
+        if ids.is_empty() {
+            return Err(eyre!("No mitch selected, select them with `Space`"));
+        }
+        Ok(ids)
+    }
+
+    /// Starts recording on the mitches with `ids` at once, see [`group`].
+    fn start_group(&mut self, ids: Vec<String>) -> color_eyre::Result<()> {
+        let (group, run) = group::start(ids.len(), self.markers()?, self.events.sender());
+        for id in &ids {
+            self.mitches
+                .send_to(id, MitchCommand::StartRecordingIn(group.clone()));
+        }
+        tokio::spawn(run);
+        Ok(())
+    }
+
+    /// Stops recording on the mitches with `ids` and marks the stop.
+    fn stop_group(&mut self, ids: Vec<String>) -> color_eyre::Result<()> {
+        let mut names = Vec::new();
+        for mitch in self
+            .mitches
+            .iter()
+            .filter(|m| ids.iter().any(|id| id == m.id()))
+        {
+            mitch.send(MitchCommand::StopRecording);
+            names.push(mitch.snapshot().name);
+        }
+        let marker = format!("group stop: {}", names.join(", "));
+        self.markers()?.push(&marker, lsl::local_clock())?;
+        Ok(())
+    }
+
     /// Handles the key events and updates the state of [`App`].
     pub fn handle_key_events(&mut self, key_event: KeyEvent) -> color_eyre::Result<()> {
         // The key events depend on the current app state
@@ -104,7 +305,17 @@ impl App {
                     }
                     KeyCode::Up => self.events.send(AppEvent::PrevMitch),
                     KeyCode::Down => self.events.send(AppEvent::NextMitch),
-                    KeyCode::Enter => self.state = AppState::Mitch,
+                    KeyCode::Enter if !self.mitches.is_empty() => self.state = AppState::Mitch,
+                    KeyCode::Char('e') => self.show_errors(),
+                    KeyCode::Char('a') => self.state = AppState::Adapters,
+                    KeyCode::Char(' ') => self.events.send(AppEvent::ToggleSelect),
+                    KeyCode::Char('r') => self.events.send(AppEvent::GroupStart),
+                    KeyCode::Char('s') => self.events.send(AppEvent::GroupStop),
+                    KeyCode::Char('p') => self.events.send(AppEvent::Pair),
+                    KeyCode::Char('w') => self.events.send(AppEvent::SwapSides),
+                    KeyCode::Char('o') => self.events.send(AppEvent::ToggleCombined),
+                    KeyCode::Char('k') => self.events.send(AppEvent::Remember),
+                    KeyCode::Char('X') => self.events.send(AppEvent::ToggleXdf),
                     // Other handlers you could add here.
                     _ => {}
                 }
@@ -122,6 +333,57 @@ impl App {
                     KeyCode::Char('d') => self.events.send(AppEvent::Disconnect),
                     KeyCode::Char('r') => self.events.send(AppEvent::StartRecord),
                     KeyCode::Char('s') => self.events.send(AppEvent::StopRecord),
+                    KeyCode::Char('l') => self.events.send(AppEvent::StartLog),
+                    KeyCode::Char('L') => self.events.send(AppEvent::StopLog),
+                    KeyCode::Char('v') => self.events.send(AppEvent::ListFiles),
+                    KeyCode::Up => self.events.send(AppEvent::PrevFile),
+                    KeyCode::Down => self.events.send(AppEvent::NextFile),
+                    KeyCode::Char('D') => self.events.send(AppEvent::Readout),
+                    KeyCode::Char('E') => self.events.send(AppEvent::EraseMemory),
+                    KeyCode::Char('g') => self.events.send(AppEvent::ToggleGapFill),
+                    KeyCode::Char('x') => self.events.send(AppEvent::ToggleCsv),
+                    KeyCode::Char('t') => self.events.send(AppEvent::SyncClock),
+                    KeyCode::Char('U') => self.events.send(AppEvent::FirmwareUpdate),
+                    KeyCode::Char('m') => self.events.send(AppEvent::NextMode),
+                    KeyCode::Char('f') => self.events.send(AppEvent::NextFrequency),
+                    KeyCode::Char('e') => self.show_errors(),
+                    _ => {}
+                }
+            }
+            AppState::Errors => {
+                if key_event.kind == KeyEventKind::Release {
+                    return Ok(());
+                }
+                match key_event.code {
+                    KeyCode::Esc | KeyCode::Char('q') => self.state = AppState::Menu,
+                    KeyCode::Char('c' | 'C') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        self.events.send(AppEvent::Quit)
+                    }
+                    KeyCode::Up => self.errors.scroll_up(),
+                    KeyCode::Down => self.errors.scroll_down(),
+                    _ => {}
+                }
+            }
+            AppState::Adapters => {
+                if key_event.kind == KeyEventKind::Release {
+                    return Ok(());
+                }
+                match key_event.code {
+                    KeyCode::Esc | KeyCode::Char('q') => self.state = AppState::Menu,
+                    KeyCode::Char('c' | 'C') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        self.events.send(AppEvent::Quit)
+                    }
+                    KeyCode::Up => self.selected_adapter = self.selected_adapter.saturating_sub(1),
+                    KeyCode::Down => {
+                        let adapters = self.adapters.as_ref().map_or(0, Vec::len);
+                        self.selected_adapter = min(self.selected_adapter + 1, adapters);
+                    }
+                    KeyCode::Enter => {
+                        if let Some(choice) = self.adapter_choice() {
+                            self.events.send(AppEvent::SelectAdapter(choice));
+                        }
+                        self.state = AppState::Menu;
+                    }
                     _ => {}
                 }
             }
@@ -133,8 +395,13 @@ impl App {
     ///
     /// The tick event is where you can update the state of your application with any logic that
     /// needs to be updated at a fixed frame rate. E.g. polling a server, updating an animation.
+    ///
+    /// The mitches poll themselves in their own tasks, a tick only redraws their snapshots and
+    /// shows a failed XDF recording.
     pub async fn tick(&mut self) -> color_eyre::Result<()> {
-        self.mitches.update().await?;
+        if let Some(error) = self.mitches.xdf.take_error() {
+            self.errors.push(None, error);
+        }
         Ok(())
     }
 
@@ -144,7 +411,38 @@ impl App {
     }
 
     pub fn next(&mut self) {
-        self.mitches.active = min(self.mitches.active + 1, self.mitches.len() - 1);
+        self.mitches.active = min(
+            self.mitches.active + 1,
+            self.mitches.len().saturating_sub(1),
+        );
+    }
+
+    /// The choice highlighted in the adapter list.
+    fn adapter_choice(&self) -> Option<AdapterChoice> {
+        match self.selected_adapter.checked_sub(1) {
+            None => Some(AdapterChoice::All),
+            Some(i) => self
+                .adapters
+                .as_ref()?
+                .get(i)
+                .map(|a| AdapterChoice::Name(a.name.clone())),
+        }
+    }
+
+    /// The marker stream, opened on first use.
+    fn markers(&mut self) -> color_eyre::Result<Arc<MarkerOutlet>> {
+        if let Some(markers) = &self.markers {
+            return Ok(markers.clone());
+        }
+        let markers = Arc::new(MarkerOutlet::new(&self.mitches.xdf)?);
+        self.markers = Some(markers.clone());
+        Ok(markers)
+    }
+
+    /// Opens the error log at the newest error.
+    pub fn show_errors(&mut self) {
+        self.errors.scroll = 0;
+        self.state = AppState::Errors;
     }
 
     pub fn prev(&mut self) {
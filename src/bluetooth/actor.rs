@@ -0,0 +1,335 @@
+//! Runs every mitch in its own task so the UI never waits on Bluetooth.
+//!
+//! The UI holds a [`MitchHandle`], sends it [`MitchCommand`]s and renders the latest
+//! [`MitchSnapshot`] the task published. The task executes the commands one after another and
+//! polls the device in between. Errors are reported as [`BluetoothEvent::Error`] and stay with
+//! the device, they never end the app.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    select,
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+
+use super::{
+    BluetoothEvent,
+    firmware::FirmwareImage,
+    firmware::FirmwareStatus,
+    group::GroupStart,
+    mitch::Mitch,
+    quality::LinkQuality,
+    registry::DeviceEntry,
+    subject::{PairStream, Side},
+    transport::MitchTransport,
+};
+use crate::event::Event;
+
+/// Interval at which the task polls state and information of its device.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Interval at which the snapshot is refreshed in between, to follow the progress of sessions.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Requests the UI sends to a mitch task.
+#[derive(Clone, Debug)]
+pub enum MitchCommand {
+    Connect,
+    Disconnect,
+    StartRecording,
+    /// Start recording together with the other mitches of the group.
+    StartRecordingIn(GroupStart),
+    StopRecording,
+    StartLogging,
+    StopLogging,
+    ListFiles,
+    PrevFile,
+    NextFile,
+    Readout,
+    EraseMemory,
+    NextMode,
+    NextFrequency,
+    ToggleGapFill,
+    ToggleCsv,
+    SyncClock,
+    /// Install `image`, also if it is older than the firmware of the device if
+    /// `allow_downgrade`.
+    UpdateFirmware {
+        image: Arc<FirmwareImage>,
+        allow_downgrade: bool,
+    },
+    /// The device advertised, with its name, signal strength and transmit power if they were
+    /// included.
+    Advertised {
+        name: Option<String>,
+        rssi: Option<i16>,
+        tx_power: Option<i16>,
+    },
+    /// Makes the mitch the given side of a subject feeding `combined` if given, or unpairs it
+    /// with `side` being `None`.
+    Pair {
+        side: Option<Side>,
+        combined: Option<Arc<PairStream>>,
+    },
+    /// Takes over the registry entry of the device.
+    Register(DeviceEntry),
+    /// The device is also in range of another adapter, reached through this transport.
+    AddRoute(Arc<dyn MitchTransport>),
+    /// The named adapter reports a link to the device.
+    LinkUp(String),
+    /// The named adapter reports the link to the device as gone.
+    LinkDown(String),
+}
+
+impl MitchCommand {
+    /// Whether the command only passes on what the discovery saw of the device.
+    fn is_report(&self) -> bool {
+        matches!(
+            self,
+            MitchCommand::Advertised { .. }
+                | MitchCommand::AddRoute(_)
+                | MitchCommand::LinkUp(_)
+                | MitchCommand::LinkDown(_)
+        )
+    }
+}
+
+/// What the UI shows of a mitch, published by its task after every command and poll.
+#[derive(Clone, Debug)]
+pub struct MitchSnapshot {
+    pub id: String,
+    pub name: String,
+    /// The settings as the registry keeps them.
+    pub entry: DeviceEntry,
+    /// One line for the device list.
+    pub summary: String,
+    /// Labelled lines for the detail view.
+    pub detail: String,
+    pub connected: bool,
+    /// Name of the adapter carrying the connection, if connected.
+    pub adapter: Option<String>,
+    /// Side of the subject the mitch belongs to, if it is paired.
+    pub side: Option<Side>,
+    pub needs_charging: bool,
+    pub quality: LinkQuality,
+    /// When the device last advertised or its link came up.
+    pub last_seen: Instant,
+    pub firmware: Option<FirmwareStatus>,
+    /// Error of the last command that failed, until a later command succeeds. Reports of the
+    /// discovery do not count as commands here.
+    pub last_error: Option<String>,
+}
+
+/// Handle to the task owning a [`Mitch`].
+///
+/// Dropping the handle ends the task, which disconnects the device.
+pub struct MitchHandle {
+    id: String,
+    commands: mpsc::UnboundedSender<MitchCommand>,
+    snapshot: watch::Receiver<MitchSnapshot>,
+    task: JoinHandle<()>,
+}
+
+impl MitchHandle {
+    /// Spawns the task controlling `mitch`, which reports its errors to `events`.
+    pub fn spawn(mitch: Mitch, events: mpsc::UnboundedSender<Event>) -> Self {
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let (publisher, snapshot) = watch::channel(mitch.snapshot(None));
+        let id = mitch.id();
+        let task = tokio::spawn(run(mitch, receiver, publisher, events));
+        Self {
+            id,
+            commands,
+            snapshot,
+            task,
+        }
+    }
+
+    /// Identifier of the device, see [`MitchTransport::id`](super::transport::MitchTransport::id).
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Queues `command`, it is executed after the ones sent before.
+    pub fn send(&self, command: MitchCommand) {
+        // The task only ends once the handle is dropped
+        let _ = self.commands.send(command);
+    }
+
+    /// The state as of the last command or poll.
+    pub fn snapshot(&self) -> MitchSnapshot {
+        self.snapshot.borrow().clone()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Ends the task and waits until it stopped recording and disconnected the device.
+    pub async fn close(self) {
+        drop(self.commands);
+        // A task that panicked has nothing left to stop
+        let _ = self.task.await;
+    }
+}
+
+impl std::fmt::Debug for MitchHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("MitchHandle")
+            .field(&self.snapshot.borrow().name)
+            .finish()
+    }
+}
+
+async fn run(
+    mut mitch: Mitch,
+    mut commands: mpsc::UnboundedReceiver<MitchCommand>,
+    snapshot: watch::Sender<MitchSnapshot>,
+    events: mpsc::UnboundedSender<Event>,
+) {
+    let report = |device: &str, error: String| {
+        // The app may be shutting down already
+        let _ = events.send(Event::Bluetooth(BluetoothEvent::Error {
+            device: Some(device.to_string()),
+            error,
+        }));
+    };
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+    let mut last_error = None;
+    loop {
+        select! {
+            command = commands.recv() => {
+                let Some(command) = command else {
+                    break;
+                };
+                let is_report = command.is_report();
+                match mitch.execute(command).await {
+                    Err(e) => {
+                        report(mitch.name(), e.to_string());
+                        last_error = Some(e.to_string());
+                    }
+                    Ok(()) if !is_report => last_error = None,
+                    Ok(()) => {}
+                }
+            }
+            _ = poll.tick() => {
+                if let Err(e) = mitch.poll().await {
+                    report(mitch.name(), e.to_string());
+                }
+            }
+            _ = refresh.tick() => {}
+        }
+        for message in mitch.take_notes() {
+            let _ = events.send(Event::Bluetooth(BluetoothEvent::Notice {
+                device: mitch.name().to_string(),
+                message,
+            }));
+        }
+        let _ = snapshot.send(mitch.snapshot(last_error.clone()));
+    }
+    // Nobody is left to report a failure to
+    if mitch.is_streaming() {
+        let _ = mitch.stop_recording().await;
+    }
+    let _ = mitch.disconnect().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::{
+        mitch::{MitchState, send_command},
+        protocol::Command,
+        sim::SimulatedMitch,
+    };
+
+    /// Waits until the snapshot of `handle` satisfies `done`.
+    async fn wait_for(handle: &MitchHandle, done: impl Fn(&MitchSnapshot) -> bool) {
+        for _ in 0..100 {
+            if done(&handle.snapshot()) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("Timed out at {:?}", handle.snapshot());
+    }
+
+    #[tokio::test]
+    async fn reports_link_loss_and_resumes() {
+        let transport = Arc::new(SimulatedMitch::new("00:00:00:00:00:03"));
+        let mitch = Mitch::new("mitch-test".to_string(), transport.clone())
+            .await
+            .unwrap();
+        let (events, mut received) = mpsc::unbounded_channel();
+        let handle = MitchHandle::spawn(mitch, events);
+        handle.send(MitchCommand::Connect);
+        handle.send(MitchCommand::StartRecording);
+        wait_for(&handle, |s| s.summary.contains("SysTx")).await;
+
+        // The link drops while the device reboots
+        send_command(transport.as_ref(), Command::Reboot)
+            .await
+            .unwrap();
+        let event = tokio::time::timeout(Duration::from_secs(3), received.recv())
+            .await
+            .unwrap();
+        let Some(Event::Bluetooth(BluetoothEvent::Error { error, .. })) = event else {
+            panic!("Unexpected event {event:?}");
+        };
+        assert!(error.starts_with("Link lost"), "{error}");
+
+        wait_for(&handle, |s| s.connected && s.summary.contains("SysTx")).await;
+        assert_eq!(handle.snapshot().last_error, None);
+
+        handle.send(MitchCommand::Disconnect);
+        wait_for(&handle, |s| !s.connected).await;
+        assert_eq!(transport.connected_via(), None);
+    }
+
+    #[tokio::test]
+    async fn close_stops_recording() {
+        let transport = Arc::new(SimulatedMitch::new("00:00:00:00:00:04"));
+        let mitch = Mitch::new("mitch-test".to_string(), transport.clone())
+            .await
+            .unwrap();
+        let (events, _received) = mpsc::unbounded_channel();
+        let handle = MitchHandle::spawn(mitch, events);
+        handle.send(MitchCommand::Connect);
+        handle.send(MitchCommand::StartRecording);
+        wait_for(&handle, |s| s.summary.contains("SysTx")).await;
+
+        handle.close().await;
+        assert_eq!(transport.connected_via(), None);
+        let mut mitch = Mitch::new("mitch-test".to_string(), transport.clone())
+            .await
+            .unwrap();
+        mitch.connect().await.unwrap();
+        assert_eq!(mitch.state(), Some(MitchState::SysIdle));
+    }
+
+    #[tokio::test]
+    async fn clears_error_once_a_command_succeeds() {
+        let transport = Arc::new(SimulatedMitch::new("00:00:00:00:00:0a"));
+        let mitch = Mitch::new("mitch-test".to_string(), transport.clone())
+            .await
+            .unwrap();
+        let (events, _received) = mpsc::unbounded_channel();
+        let handle = MitchHandle::spawn(mitch, events);
+        // Recording needs a link
+        handle.send(MitchCommand::StartRecording);
+        wait_for(&handle, |s| s.last_error.is_some()).await;
+        handle.send(MitchCommand::Advertised {
+            name: None,
+            rssi: Some(-60),
+            tx_power: None,
+        });
+        handle.send(MitchCommand::Connect);
+        wait_for(&handle, |s| s.connected).await;
+        assert_eq!(handle.snapshot().last_error, None);
+        handle.close().await;
+    }
+}
@@ -0,0 +1,166 @@
+//! The Bluetooth adapters to scan with, their state and the connections they carry.
+//!
+//! A single adapter only holds a handful of connections, so mitches are spread over all adapters
+//! they are in range of: every connection takes an [`AdapterSlot`] from the shared
+//! [`AdapterLoad`], and a mitch connects through the adapter with the fewest slots taken.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// Which adapters to scan with, given by `--adapter` or chosen in the UI.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum AdapterChoice {
+    /// Every adapter of the system.
+    #[default]
+    All,
+    /// The adapter at this position in the list of the system.
+    Index(usize),
+    /// The adapter whose name, like `hci1`, is this or starts with it followed by a space.
+    Name(String),
+}
+
+impl AdapterChoice {
+    /// Whether the adapter called `name` at position `index` in the list of the system is chosen.
+    pub fn picks(&self, index: usize, name: &str) -> bool {
+        match self {
+            AdapterChoice::All => true,
+            AdapterChoice::Index(i) => *i == index,
+            AdapterChoice::Name(n) => name == n || name.split_whitespace().next() == Some(n),
+        }
+    }
+}
+
+impl FromStr for AdapterChoice {
+    type Err = std::convert::Infallible;
+
+    /// Parses `all`, a number as index and anything else as name.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "all" {
+            return Ok(AdapterChoice::All);
+        }
+        Ok(match s.parse() {
+            Ok(index) => AdapterChoice::Index(index),
+            Err(_) => AdapterChoice::Name(s.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for AdapterChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdapterChoice::All => write!(f, "all adapters"),
+            AdapterChoice::Index(index) => write!(f, "adapter {index}"),
+            AdapterChoice::Name(name) => write!(f, "adapter {name}"),
+        }
+    }
+}
+
+/// What the discovery does with an adapter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdapterState {
+    /// Not chosen for scanning.
+    Unused,
+    /// Chosen, the scan is being set up.
+    Starting,
+    /// Chosen but off, scanning starts once it is powered on.
+    PoweredOff,
+    Scanning,
+}
+
+impl fmt::Display for AdapterState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdapterState::Unused => write!(f, "unused"),
+            AdapterState::Starting => write!(f, "starting"),
+            AdapterState::PoweredOff => write!(f, "powered off"),
+            AdapterState::Scanning => write!(f, "scanning"),
+        }
+    }
+}
+
+/// An adapter of the system as seen by the discovery.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AdapterInfo {
+    pub name: String,
+    pub state: AdapterState,
+}
+
+/// Explanation for the screen shown instead of the device list while no adapter scans, given
+/// the adapters the discovery reported, `None` before the first report.
+pub fn message(adapters: Option<&[AdapterInfo]>) -> Option<String> {
+    let Some(adapters) = adapters else {
+        return Some("Looking for Bluetooth adapters...".to_string());
+    };
+    let states = || adapters.iter().map(|a| a.state);
+    if states().any(|s| matches!(s, AdapterState::Starting | AdapterState::Scanning)) {
+        return None;
+    }
+    if adapters.is_empty() {
+        return Some(
+            "No Bluetooth adapter found.\nScanning starts once one is plugged in.".to_string(),
+        );
+    }
+    let off: Vec<&str> = adapters
+        .iter()
+        .filter(|a| a.state == AdapterState::PoweredOff)
+        .map(|a| a.name.as_str())
+        .collect();
+    if off.is_empty() {
+        return Some("No Bluetooth adapter chosen.\nPress `a` to choose one.".to_string());
+    }
+    Some(format!(
+        "Bluetooth adapter {} powered off.\nScanning starts once it is powered on.",
+        off.join(", ")
+    ))
+}
+
+/// Number of connections per adapter, shared by all mitches.
+#[derive(Clone, Debug, Default)]
+pub struct AdapterLoad(Arc<Mutex<BTreeMap<String, usize>>>);
+
+impl AdapterLoad {
+    /// Connections through the adapter called `adapter`.
+    pub fn connections(&self, adapter: &str) -> usize {
+        self.0.lock().unwrap().get(adapter).copied().unwrap_or(0)
+    }
+
+    /// Counts a connection through `adapter` until the returned slot is dropped.
+    pub fn attach(&self, adapter: &str) -> AdapterSlot {
+        *self
+            .0
+            .lock()
+            .unwrap()
+            .entry(adapter.to_string())
+            .or_default() += 1;
+        AdapterSlot {
+            load: self.clone(),
+            adapter: adapter.to_string(),
+        }
+    }
+}
+
+/// A connection counted by an [`AdapterLoad`].
+#[derive(Debug)]
+pub struct AdapterSlot {
+    load: AdapterLoad,
+    adapter: String,
+}
+
+impl AdapterSlot {
+    /// Name of the adapter the connection goes through.
+    pub fn adapter(&self) -> &str {
+        &self.adapter
+    }
+}
+
+impl Drop for AdapterSlot {
+    fn drop(&mut self) {
+        if let Some(count) = self.load.0.lock().unwrap().get_mut(&self.adapter) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
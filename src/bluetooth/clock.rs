@@ -0,0 +1,75 @@
+//! Relation between the real-time clock of a mitch and the LSL clock of the host.
+//!
+//! The device clock counts microseconds since the Unix epoch and is set from the host wall clock.
+//! Its offset to [`lsl::local_clock`] is estimated NTP style: the device time read in a command
+//! exchange is assumed to be taken halfway through the round trip, and the exchange with the
+//! shortest round trip wins.
+//!
+//! Firmware without a clock rejects the time commands, its samples are stamped by the host. The
+//! time commands are not confirmed for the mitch firmware, see [`protocol`](super::protocol).
+//! So any reply that does not fit is taken as the firmware having no clock.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{info::query, protocol::Command, transport::MitchTransport};
+
+/// Command exchanges per offset estimate.
+const SYNC_EXCHANGES: usize = 8;
+
+/// Offset of a device clock to the LSL clock.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockSync {
+    /// Device clock minus LSL clock, in seconds.
+    pub offset: f64,
+    /// Round trip time of the exchange the offset was taken from, in seconds. Bounds the error
+    /// of the offset to half of it.
+    pub rtt: f64,
+}
+
+impl ClockSync {
+    /// Estimates the offset from a series of [`Command::GetTime`] exchanges, `None` if the
+    /// firmware has no clock.
+    pub async fn estimate(transport: &dyn MitchTransport) -> color_eyre::Result<Option<Self>> {
+        let Some(mut best) = Self::exchange(transport).await? else {
+            return Ok(None);
+        };
+        for _ in 1..SYNC_EXCHANGES {
+            if let Some(sync) = Self::exchange(transport).await?
+                && sync.rtt < best.rtt
+            {
+                best = sync;
+            }
+        }
+        Ok(Some(best))
+    }
+
+    async fn exchange(transport: &dyn MitchTransport) -> color_eyre::Result<Option<Self>> {
+        let sent = lsl::local_clock();
+        let reply = query(transport, Command::GetTime).await?;
+        let received = lsl::local_clock();
+        let Ok(time) = reply.and_then(|r| r.time()) else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            offset: micros_to_secs(time) - (sent + received) / 2.0,
+            rtt: received - sent,
+        }))
+    }
+
+    /// Maps a device time in microseconds since the Unix epoch onto the LSL clock.
+    pub fn to_lsl(&self, device_time: u64) -> f64 {
+        micros_to_secs(device_time) - self.offset
+    }
+}
+
+/// Sets the device clock to the host wall clock, if the firmware has one.
+pub async fn set_device_clock(transport: &dyn MitchTransport) -> color_eyre::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_micros() as u64;
+    // Firmware without a clock rejects the command
+    let _ = query(transport, Command::SetTime(now)).await?;
+    Ok(())
+}
+
+fn micros_to_secs(micros: u64) -> f64 {
+    micros as f64 / 1e6
+}
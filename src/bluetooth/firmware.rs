@@ -0,0 +1,311 @@
+//! Firmware updates through the bootloader states of a mitch.
+//!
+//! A firmware file starts with a 16 byte header followed by the image:
+//!
+//! | bytes  | content                                  |
+//! |--------|------------------------------------------|
+//! | 0..4   | magic `MFW1`                             |
+//! | 4      | hardware revision the image is built for |
+//! | 5..8   | version major, minor, patch              |
+//! | 8..12  | image size, `u32` LE                     |
+//! | 12..16 | CRC-32 of the image, `u32` LE            |
+//!
+//! An image older than the firmware on the device is only installed if downgrades are allowed.
+//! The image is sent in chunks as large as the MTU of the link allows. btleplug does not report
+//! the MTU it negotiated, so over Bluetooth the chunks always fit the smallest MTU of 23 bytes
+//! and carry 14 bytes of the image each. Only the simulator reports an MTU to fit them to.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use color_eyre::eyre::eyre;
+use tokio::task::JoinHandle;
+
+use super::{
+    mitch::{MitchState, send_command},
+    protocol::{Command, FirmwareVersion},
+    readout::crc32,
+    transport::{ATT_HEADER_LEN, DEFAULT_MTU, MitchTransport},
+};
+
+const MAGIC: &[u8; 4] = b"MFW1";
+const HEADER_LEN: usize = 16;
+
+/// Attempts per firmware chunk before the update is given up.
+const CHUNK_RETRIES: u32 = 3;
+/// Delay before the first retry of a failed chunk, doubled for every further one.
+const CHUNK_RETRY_DELAY: Duration = Duration::from_millis(200);
+/// Bytes of a chunk command taken by command id, length and chunk offset.
+const CHUNK_HEADER_LEN: usize = 6;
+/// Attempts to reach the device again after it rebooted.
+const RECONNECT_ATTEMPTS: usize = 10;
+/// Delay between reconnection attempts.
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+/// A validated firmware file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FirmwareImage {
+    pub hardware: u8,
+    pub version: FirmwareVersion,
+    pub crc: u32,
+    pub data: Vec<u8>,
+}
+
+impl FirmwareImage {
+    pub fn load(path: &Path) -> color_eyre::Result<Self> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    /// Parses a firmware file and checks the image against its header.
+    pub fn parse(bytes: &[u8]) -> color_eyre::Result<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[0..4] != MAGIC {
+            return Err(eyre!("Not a mitch firmware file"));
+        }
+        let size = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize;
+        let crc = u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]);
+        let data = &bytes[HEADER_LEN..];
+        if data.len() != size {
+            return Err(eyre!(
+                "Firmware image has {} bytes, header announces {size}",
+                data.len()
+            ));
+        }
+        if crc32(data) != crc {
+            return Err(eyre!("Firmware image is corrupt"));
+        }
+        Ok(Self {
+            hardware: bytes[4],
+            version: FirmwareVersion {
+                major: bytes[5],
+                minor: bytes[6],
+                patch: bytes[7],
+            },
+            crc,
+            data: data.to_vec(),
+        })
+    }
+}
+
+/// Progress of a [`FirmwareUpdate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FirmwareStatus {
+    Checking,
+    EnteringBootloader,
+    Transferring {
+        sent: usize,
+        size: usize,
+    },
+    Verifying,
+    Rebooting,
+    /// The device runs the new firmware.
+    Done(FirmwareVersion),
+    Failed(String),
+}
+
+impl FirmwareStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, FirmwareStatus::Done(_) | FirmwareStatus::Failed(_))
+    }
+}
+
+/// Handle to the task updating the firmware of a connected mitch.
+pub struct FirmwareUpdate {
+    status: Arc<Mutex<FirmwareStatus>>,
+    task: JoinHandle<()>,
+}
+
+impl FirmwareUpdate {
+    /// Spawns the task installing `image`, which refuses an older image unless
+    /// `allow_downgrade`.
+    pub fn start(
+        transport: Arc<dyn MitchTransport>,
+        image: Arc<FirmwareImage>,
+        allow_downgrade: bool,
+    ) -> Self {
+        let status = Arc::new(Mutex::new(FirmwareStatus::Checking));
+        let task_status = status.clone();
+        let task = tokio::spawn(async move {
+            let result = update(transport.as_ref(), &image, allow_downgrade, &task_status).await;
+            if let Err(e) = &result {
+                // Leave the link in a known state, the device is either back in the
+                // application or stuck in the bootloader waiting for a new attempt
+                let _ = transport.disconnect().await;
+                *task_status.lock().unwrap() = FirmwareStatus::Failed(e.to_string());
+            }
+        });
+        Self { status, task }
+    }
+
+    pub fn status(&self) -> FirmwareStatus {
+        self.status.lock().unwrap().clone()
+    }
+}
+
+impl Drop for FirmwareUpdate {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn update(
+    transport: &dyn MitchTransport,
+    image: &FirmwareImage,
+    allow_downgrade: bool,
+    status: &Mutex<FirmwareStatus>,
+) -> color_eyre::Result<()> {
+    let set = |s: FirmwareStatus| *status.lock().unwrap() = s;
+
+    let state = send_command(transport, Command::GetState).await?.state()?;
+    // A device stuck in the bootloader from an earlier attempt is updated right away
+    if !matches!(state, MitchState::BootIdle | MitchState::BootDownload) {
+        let (hardware, version) = send_command(transport, Command::GetFirmwareVersion)
+            .await?
+            .firmware_version()?;
+        if hardware != image.hardware {
+            return Err(eyre!(
+                "Firmware is built for hardware revision {}, device has {hardware}",
+                image.hardware
+            ));
+        }
+        if version > image.version && !allow_downgrade {
+            return Err(eyre!(
+                "Device runs {version}, newer than {}, start with --allow-downgrade to install it",
+                image.version
+            ));
+        }
+        set(FirmwareStatus::EnteringBootloader);
+        // The device may reboot before its reply can be read
+        let _ = send_command(transport, Command::EnterBootloader).await;
+        reconnect(transport, MitchState::BootIdle).await?;
+    }
+
+    send_command(
+        transport,
+        Command::FirmwareBegin {
+            size: image.data.len() as u32,
+            crc: image.crc,
+            version: image.version,
+        },
+    )
+    .await?;
+    let size = image.data.len();
+    let chunk_len = chunk_len(transport);
+    for (i, chunk) in image.data.chunks(chunk_len).enumerate() {
+        let offset = i * chunk_len;
+        let command = Command::FirmwareChunk {
+            offset: offset as u32,
+            data: chunk.to_vec(),
+        };
+        let mut attempt = 0;
+        while let Err(e) = send_command(transport, command.clone()).await {
+            attempt += 1;
+            if attempt >= CHUNK_RETRIES {
+                return Err(e.wrap_err(format!("Transfer failed at byte {offset}")));
+            }
+            // A busy link or device needs time to recover
+            tokio::time::sleep(CHUNK_RETRY_DELAY * 2_u32.pow(attempt - 1)).await;
+        }
+        set(FirmwareStatus::Transferring {
+            sent: offset + chunk.len(),
+            size,
+        });
+    }
+
+    set(FirmwareStatus::Verifying);
+    send_command(transport, Command::FirmwareVerify).await?;
+
+    set(FirmwareStatus::Rebooting);
+    let _ = send_command(transport, Command::Reboot).await;
+    reconnect(transport, MitchState::SysIdle).await?;
+    let (_, version) = send_command(transport, Command::GetFirmwareVersion)
+        .await?
+        .firmware_version()?;
+    if version != image.version {
+        return Err(eyre!(
+            "Device runs {version} after the update instead of {}",
+            image.version
+        ));
+    }
+    set(FirmwareStatus::Done(version));
+    Ok(())
+}
+
+/// Largest chunk that fits into a single write on the link to `transport`.
+///
+/// Without a known MTU the smallest one every link supports is assumed, which is always the case
+/// over btleplug.
+fn chunk_len(transport: &dyn MitchTransport) -> usize {
+    let mtu = transport.mtu().unwrap_or(DEFAULT_MTU);
+    (mtu.saturating_sub(ATT_HEADER_LEN + CHUNK_HEADER_LEN)).clamp(1, Command::MAX_CHUNK)
+}
+
+/// Waits for the device to reboot and connects again once it reports `state`.
+async fn reconnect(transport: &dyn MitchTransport, state: MitchState) -> color_eyre::Result<()> {
+    for _ in 0..RECONNECT_ATTEMPTS {
+        tokio::time::sleep(RECONNECT_DELAY).await;
+        let _ = transport.disconnect().await;
+        if transport.connect().await.is_err() || transport.discover().await.is_err() {
+            continue;
+        }
+        if let Ok(response) = send_command(transport, Command::GetState).await
+            && response.state()? == state
+        {
+            return Ok(());
+        }
+    }
+    Err(eyre!("Device did not come back in {state:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::sim::{SIM_HARDWARE, SimulatedMitch};
+
+    fn image(major: u8, minor: u8) -> FirmwareImage {
+        let data: Vec<u8> = (0..1000_u32).map(|i| i as u8).collect();
+        FirmwareImage {
+            hardware: SIM_HARDWARE,
+            version: FirmwareVersion {
+                major,
+                minor,
+                patch: 0,
+            },
+            crc: crc32(&data),
+            data,
+        }
+    }
+
+    async fn connected(transport: SimulatedMitch) -> SimulatedMitch {
+        transport.connect().await.unwrap();
+        transport.discover().await.unwrap();
+        transport
+    }
+
+    #[tokio::test]
+    async fn transfers_chunks_within_mtu() {
+        // The simulated link fails writes beyond its MTU
+        let transport = connected(SimulatedMitch::new("00:00:00:00:00:05").with_mtu(40)).await;
+        assert_eq!(chunk_len(&transport), 31);
+        let image = image(1, 1);
+        let status = Mutex::new(FirmwareStatus::Checking);
+        update(&transport, &image, false, &status).await.unwrap();
+        assert_eq!(*status.lock().unwrap(), FirmwareStatus::Done(image.version));
+    }
+
+    #[tokio::test]
+    async fn refuses_downgrade() {
+        let transport = connected(SimulatedMitch::new("00:00:00:00:00:06")).await;
+        let image = image(0, 9);
+        let status = Mutex::new(FirmwareStatus::Checking);
+        let error = update(&transport, &image, false, &status)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("newer than 0.9.0"), "{error}");
+
+        update(&transport, &image, true, &status).await.unwrap();
+        assert_eq!(*status.lock().unwrap(), FirmwareStatus::Done(image.version));
+    }
+}
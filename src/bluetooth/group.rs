@@ -0,0 +1,125 @@
+//! Starting the recordings of several mitches at once.
+//!
+//! Every mitch of the group first gets ready to stream: it subscribes to its data and opens its
+//! outlet, then reports back and waits. Once all of them are ready, a single signal releases
+//! them and each sends the start command right away, so the only spread left is that of the
+//! Bluetooth links. If any mitch fails or takes too long, none of them starts. The time each
+//! mitch actually started at is written to the marker stream.
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    sync::{mpsc, watch},
+    time::timeout,
+};
+
+use super::BluetoothEvent;
+use crate::{event::Event, markers::MarkerOutlet};
+
+/// Time the mitches get to become ready, and then to start.
+const GROUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a mitch of the group tells the coordinating task.
+#[derive(Clone, Debug)]
+pub enum GroupReport {
+    /// Ready to stream, waiting for the signal.
+    Ready,
+    /// Streaming since the LSL time `time`, as close as the device tells.
+    Started {
+        time: f64,
+    },
+    Failed,
+}
+
+/// The part of a group start handed to each of its mitches.
+#[derive(Clone, Debug)]
+pub struct GroupStart {
+    reports: mpsc::UnboundedSender<(String, GroupReport)>,
+    go: watch::Receiver<bool>,
+}
+
+impl GroupStart {
+    /// Tells the coordinating task about the mitch named `name`.
+    pub fn report(&self, name: &str, report: GroupReport) {
+        // The group may have been given up already
+        let _ = self.reports.send((name.to_string(), report));
+    }
+
+    /// Waits for the signal to start, `false` if the group was given up instead.
+    pub async fn go(&mut self) -> bool {
+        self.go.wait_for(|go| *go).await.is_ok()
+    }
+}
+
+/// Prepares a group start of `count` mitches. Each of them gets a clone of the returned
+/// [`GroupStart`], then the future runs the start.
+pub fn start(
+    count: usize,
+    markers: Arc<MarkerOutlet>,
+    events: mpsc::UnboundedSender<Event>,
+) -> (GroupStart, impl Future<Output = ()>) {
+    let (reports, receiver) = mpsc::unbounded_channel();
+    let (go, waiting) = watch::channel(false);
+    let group = GroupStart {
+        reports,
+        go: waiting,
+    };
+    let run = async move {
+        if let Err(error) = coordinate(count, receiver, go, &markers).await {
+            // The app may be shutting down already
+            let _ = events.send(Event::Bluetooth(BluetoothEvent::Error {
+                device: None,
+                error,
+            }));
+        }
+    };
+    (group, run)
+}
+
+async fn coordinate(
+    count: usize,
+    mut reports: mpsc::UnboundedReceiver<(String, GroupReport)>,
+    go: watch::Sender<bool>,
+    markers: &MarkerOutlet,
+) -> Result<(), String> {
+    let mut ready = 0;
+    while ready < count {
+        match timeout(GROUP_TIMEOUT, reports.recv()).await {
+            Ok(Some((_, GroupReport::Ready))) => ready += 1,
+            // Dropping the signal makes the waiting mitches give up
+            Ok(Some((name, _))) => return Err(format!("Group start cancelled, {name} failed")),
+            Ok(None) | Err(_) => {
+                return Err(format!(
+                    "Group start cancelled, only {ready} of {count} mitches got ready"
+                ));
+            }
+        }
+    }
+    let _ = go.send(true);
+
+    let mut started = Vec::new();
+    for _ in 0..count {
+        match timeout(GROUP_TIMEOUT, reports.recv()).await {
+            Ok(Some((name, GroupReport::Started { time }))) => started.push((name, time)),
+            // The mitch reports its error itself
+            Ok(Some(_)) => {}
+            Ok(None) | Err(_) => break,
+        }
+    }
+    if let Some(first) = started.iter().map(|(_, t)| *t).reduce(f64::min) {
+        let offsets: Vec<String> = started
+            .iter()
+            .map(|(name, time)| format!("{name} +{:.1} ms", (time - first) * 1000.0))
+            .collect();
+        markers
+            .push(&format!("group start: {}", offsets.join(", ")), first)
+            .map_err(|e| format!("Writing the group start marker failed: {e}"))?;
+    }
+    if started.len() < count {
+        return Err(format!(
+            "Group start incomplete, {} of {count} mitches started",
+            started.len()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+//! Information about a mitch that is queried from the device and cached between ticks.
+
+use std::collections::BTreeSet;
+
+use super::{
+    mitch::send_command,
+    protocol::{Command, FirmwareVersion, ProtocolError, Response},
+    transport::MitchTransport,
+};
+
+/// Battery charge in percent below which a device should be charged before a session.
+pub const LOW_BATTERY: u8 = 20;
+
+/// Log memory of a device in bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Memory {
+    pub free: u32,
+    pub total: u32,
+}
+
+impl Memory {
+    pub fn free_percent(&self) -> u8 {
+        (self.free as u64 * 100 / self.total.max(1) as u64) as u8
+    }
+}
+
+/// The last known device information, `None` where it was not read yet or the firmware does
+/// not support the query.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Battery charge in percent.
+    pub battery: Option<u8>,
+    pub hardware: Option<u8>,
+    pub firmware: Option<FirmwareVersion>,
+    /// Unique id of the device as printed on the insole.
+    pub serial: Option<String>,
+    pub memory: Option<Memory>,
+    /// Ids of the queries the device did not answer properly, they are not sent again.
+    rejected: BTreeSet<u8>,
+}
+
+impl DeviceInfo {
+    /// Reads battery charge and free memory, and the fixed values not known yet.
+    ///
+    /// Only fails if the device cannot be reached. Returns a note for every query the device
+    /// rejected or answered with an invalid reply, which is skipped from then on.
+    pub async fn refresh(
+        &mut self,
+        transport: &dyn MitchTransport,
+    ) -> color_eyre::Result<Vec<String>> {
+        let mut notes = Vec::new();
+        if let Some(battery) = self
+            .ask(transport, Command::GetBatteryCharge, &mut notes, |r| {
+                r.battery_charge()
+            })
+            .await?
+        {
+            self.battery = Some(battery);
+        }
+        if let Some((free, total)) = self
+            .ask(transport, Command::GetMemoryStatus, &mut notes, |r| {
+                r.memory_status()
+            })
+            .await?
+        {
+            self.memory = Some(Memory { free, total });
+        }
+        if self.firmware.is_none()
+            && let Some((hardware, firmware)) = self
+                .ask(transport, Command::GetFirmwareVersion, &mut notes, |r| {
+                    r.firmware_version()
+                })
+                .await?
+        {
+            self.hardware = Some(hardware);
+            self.firmware = Some(firmware);
+        }
+        if self.serial.is_none()
+            && let Some(id) = self
+                .ask(transport, Command::GetDeviceId, &mut notes, |r| {
+                    r.device_id()
+                })
+                .await?
+        {
+            self.serial = Some(format!("{id:016X}"));
+        }
+        Ok(notes)
+    }
+
+    /// Sends `command` unless the device rejected it before and reads the reply with `parse`.
+    async fn ask<T>(
+        &mut self,
+        transport: &dyn MitchTransport,
+        command: Command,
+        notes: &mut Vec<String>,
+        parse: impl FnOnce(Response) -> Result<T, ProtocolError>,
+    ) -> color_eyre::Result<Option<T>> {
+        let id = command.id();
+        if self.rejected.contains(&id) {
+            return Ok(None);
+        }
+        match query(transport, command).await?.and_then(parse) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => {
+                self.rejected.insert(id);
+                notes.push(format!(
+                    "Query {id:#04x} not supported, no longer sent: {e}"
+                ));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Sends the rejected queries again, as new firmware may support them.
+    pub fn forget_rejected(&mut self) {
+        self.rejected.clear();
+    }
+
+    pub fn needs_charging(&self) -> bool {
+        self.battery.is_some_and(|b| b < LOW_BATTERY)
+    }
+
+    /// One line summary for the device list.
+    pub fn summary(&self) -> String {
+        let battery = self.battery.map_or("--".to_string(), |b| format!("{b}%"));
+        let firmware = self.firmware.map_or("?".to_string(), |f| f.to_string());
+        let memory = self
+            .memory
+            .map_or("--".to_string(), |m| format!("{}%", m.free_percent()));
+        format!("battery {battery}, fw {firmware}, free {memory}")
+    }
+
+    /// Labelled lines for the detail view, one per value.
+    pub fn details(&self) -> Vec<String> {
+        let battery = match self.battery {
+            Some(b) if self.needs_charging() => format!("battery {b} % (needs charging)"),
+            Some(b) => format!("battery {b} %"),
+            None => "battery unknown".to_string(),
+        };
+        let firmware = match (self.firmware, self.hardware) {
+            (Some(f), Some(h)) => format!("firmware {f} on hardware revision {h}"),
+            (Some(f), None) => format!("firmware {f}"),
+            (None, _) => "firmware unknown".to_string(),
+        };
+        let serial = match &self.serial {
+            Some(serial) => format!("serial {serial}"),
+            None => "serial unknown".to_string(),
+        };
+        let memory = match self.memory {
+            Some(m) => format!(
+                "free memory {} of {} ({} %)",
+                mebibytes(m.free),
+                mebibytes(m.total),
+                m.free_percent()
+            ),
+            None => "free memory unknown".to_string(),
+        };
+        vec![battery, firmware, serial, memory]
+    }
+}
+
+fn mebibytes(bytes: u32) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+/// Sends `command`, with the inner error telling why the device did not answer it properly.
+///
+/// Most queries use unconfirmed command ids, see [`protocol`](super::protocol). So any
+/// [`ProtocolError`] is taken as the firmware not supporting the command, only a failed
+/// exchange is an error.
+pub(super) async fn query(
+    transport: &dyn MitchTransport,
+    command: Command,
+) -> color_eyre::Result<Result<Response, ProtocolError>> {
+    match send_command(transport, command).await {
+        Ok(response) => Ok(Ok(response)),
+        Err(e) => e.downcast::<ProtocolError>().map(Err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn details_label_every_value() {
+        let info = DeviceInfo {
+            battery: Some(12),
+            hardware: Some(1),
+            firmware: Some(FirmwareVersion {
+                major: 1,
+                minor: 2,
+                patch: 3,
+            }),
+            serial: Some("00000000000000AB".to_string()),
+            memory: Some(Memory {
+                free: 2 * 1024 * 1024,
+                total: 8 * 1024 * 1024,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            info.details(),
+            [
+                "battery 12 % (needs charging)",
+                "firmware 1.2.3 on hardware revision 1",
+                "serial 00000000000000AB",
+                "free memory 2.0 MiB of 8.0 MiB (25 %)",
+            ]
+        );
+        assert_eq!(DeviceInfo::default().details()[0], "battery unknown");
+    }
+}
@@ -1,59 +1,128 @@
-use std::cmp::max;
-
-use btleplug::{
-    api::{Peripheral as _, WriteType},
-    platform::Peripheral,
+use std::{
+    cmp::{max, min},
+    collections::HashSet,
+    path::Path,
+    time::{Duration, Instant},
 };
+
+use std::sync::Arc;
+
 use color_eyre::eyre::eyre;
-use futures::{StreamExt, executor::block_on};
-use lsl::{Pushable, StreamInfo, StreamOutlet};
+use futures::executor::block_on;
 use ratatui::{
     buffer::Buffer,
-    layout::{Constraint, Direction, Flex, Layout, Rect},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Style},
-    widgets::{Block, Borders, Paragraph, Widget, WidgetRef},
+    text::{Line, Span},
+    widgets::{Block, Borders, Gauge, Paragraph, Widget, WidgetRef},
 };
 use std::fmt;
-use tokio::{
-    select,
-    sync::{Mutex, watch},
-};
+use tokio::sync::mpsc;
 use uuid::{Uuid, uuid};
 
+use super::{
+    actor::{MitchCommand, MitchHandle, MitchSnapshot},
+    adapter::{AdapterLoad, AdapterSlot},
+    clock::{self, ClockSync},
+    firmware::{FirmwareImage, FirmwareStatus, FirmwareUpdate},
+    group::{GroupReport, GroupStart},
+    info::DeviceInfo,
+    protocol::{Command, ProtocolError, Response, StreamConfig},
+    quality::LinkQuality,
+    readout::{LogFile, READOUT_DIR, ReadoutSession, ReadoutStatus},
+    reconnect::{Reconnect, ReconnectPolicy},
+    recorder::{CsvRecorder, RECORDING_DIR},
+    registry::{DeviceEntry, Role},
+    session::{Sinks, StreamSession, StreamStats},
+    subject::{PairStream, Side, Subject},
+    transport::MitchTransport,
+};
+use crate::{event::Event, ui::center, xdf::XdfRecorder};
+
 pub const COMMAND_CHAR: Uuid = uuid!("d5913036-2d8a-41ee-85b9-4e361aa5c8a7");
 pub const DATA_CHAR: Uuid = uuid!("09bf2c52-d1d9-c0b7-4145-475964544307");
 
-#[derive(Clone)]
+/// Default time without advertisements after which a disconnected mitch is marked stale.
+pub const STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// Time between reads of battery charge and free memory, which change only slowly.
+const INFO_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct Mitch {
+    /// Name the device advertises.
     name: String,
-    per: Peripheral,
+    /// Name given in the registry, shown and announced instead.
+    alias: Option<String>,
+    /// Where the device is worn, as registered.
+    role: Option<Role>,
+    /// Subject the device is registered to.
+    subject: Option<String>,
+    /// Route of the current or last connection.
+    transport: Arc<dyn MitchTransport>,
+    /// Every route to the device, one per adapter it was discovered with.
+    routes: Vec<Arc<dyn MitchTransport>>,
+    /// Connections per adapter, shared with the other mitches to pick the least used route.
+    load: AdapterLoad,
+    /// Counts the connection with its adapter while connected.
+    slot: Option<AdapterSlot>,
+    /// Recording of the streams of all mitches.
+    xdf: XdfRecorder,
     connected: bool,
     state: Option<MitchState>,
+    config: StreamConfig,
+    /// Whether the next recording replaces lost packets by NaN samples.
+    fill_gaps: bool,
+    /// Whether the next recording also writes the samples to a CSV file.
+    record_csv: bool,
+    session: Option<StreamSession>,
+    /// Log files in the memory of the device, as of the last [`Mitch::list_files`].
+    files: Vec<LogFile>,
+    selected_file: usize,
+    readout: Option<ReadoutSession>,
+    /// Indices of the log files downloaded and verified since the last listing.
+    verified: Vec<u8>,
+    firmware: Option<FirmwareUpdate>,
+    /// How the last firmware update ended, kept once its task is gone.
+    firmware_result: Option<FirmwareStatus>,
+    /// Kept after disconnecting so the list still shows the last known battery charge.
+    info: DeviceInfo,
+    /// When [`Mitch::info`] was last read from the device.
+    info_read: Option<Instant>,
+    /// Offset of the device clock, estimated on connecting.
+    clock: Option<ClockSync>,
+    policy: ReconnectPolicy,
+    /// Set while the link is lost and being restored.
+    reconnect: Option<Reconnect>,
+    /// Stream configuration to restart once the lost link is restored.
+    resume: Option<StreamConfig>,
+    /// Recent signal strength and link performance.
+    quality: LinkQuality,
+    /// Side of the subject the mitch belongs to, if it is paired.
+    side: Option<Side>,
+    /// Combined stream of the subject, fed from the next recording on.
+    combined: Option<Arc<PairStream>>,
+    /// When the device last advertised or its link came up.
+    last_seen: Instant,
+    /// Failures that need no attention, logged without a toast by the task of the mitch.
+    notes: Vec<String>,
 }
 
 impl Drop for Mitch {
     fn drop(&mut self) {
         if self.connected {
-            let _ = block_on(self.per.disconnect());
+            let _ = block_on(self.transport.disconnect());
         }
     }
 }
 
 impl fmt::Debug for Mitch {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        #[derive(Debug)]
-        #[allow(dead_code)]
-        struct DebugMitch<'a> {
-            name: &'a String,
-            connected: bool,
-            state: Option<MitchState>,
-        }
-        let dbg = DebugMitch {
-            name: &self.name,
-            connected: self.connected,
-            state: self.state,
-        };
-        fmt::Debug::fmt(&dbg, f)
+        f.debug_struct("Mitch")
+            .field("name", &self.name())
+            .field("id", &self.id())
+            .field("connected", &self.connected)
+            .field("state", &self.state)
+            .finish_non_exhaustive()
     }
 }
 
@@ -89,154 +158,917 @@ impl TryFrom<u8> for MitchState {
     }
 }
 
-enum Commands {
-    GetState,
-    StartStream,
-    StopStream,
-}
-
-impl AsRef<[u8]> for Commands {
-    fn as_ref(&self) -> &[u8] {
-        match self {
-            Commands::GetState => &[130, 0],
-            Commands::StartStream => &[0x02, 0x03, 0xF8, 0x04, 0x04],
-            Commands::StopStream => &[0x02, 0x01, 0x02],
-        }
-    }
-}
-
-struct MyInfo(StreamInfo);
-unsafe impl Send for MyInfo {}
-
-struct MyOutlet(StreamOutlet);
-unsafe impl Send for MyOutlet {}
-
 impl Mitch {
-    pub async fn new(name: String, per: Peripheral) -> color_eyre::Result<Self> {
+    pub async fn new(name: String, transport: Arc<dyn MitchTransport>) -> color_eyre::Result<Self> {
         Ok(Self {
             name,
-            per,
+            alias: None,
+            role: None,
+            subject: None,
+            routes: vec![transport.clone()],
+            transport,
+            load: AdapterLoad::default(),
+            slot: None,
+            xdf: XdfRecorder::default(),
             connected: false,
             state: None,
+            config: StreamConfig::default(),
+            fill_gaps: false,
+            record_csv: false,
+            session: None,
+            files: Vec::new(),
+            selected_file: 0,
+            readout: None,
+            verified: Vec::new(),
+            firmware: None,
+            firmware_result: None,
+            info: DeviceInfo::default(),
+            info_read: None,
+            clock: None,
+            policy: ReconnectPolicy::default(),
+            reconnect: None,
+            resume: None,
+            quality: LinkQuality::default(),
+            side: None,
+            combined: None,
+            last_seen: Instant::now(),
+            notes: Vec::new(),
         })
     }
 
+    /// The alias of the device if it has one, its advertised name otherwise.
+    pub fn name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn id(&self) -> String {
+        self.transport.id()
+    }
+
     pub fn name_with_state(&self) -> String {
-        format!("{} - {:?}", self.name, self.state)
-    }
-
-    pub async fn start_lsl_stream(&self) -> color_eyre::Result<watch::Sender<bool>> {
-        let mut s = self.per.notifications().await?;
-        let (tx, mut rx) = watch::channel(true);
-        let stream_name = self.name.clone();
-        tokio::spawn(async move {
-            let info = MyInfo(
-                StreamInfo::new(
-                    &stream_name,
-                    "Motion",
-                    1,
-                    50.0,
-                    lsl::ChannelFormat::Double64,
-                    &stream_name,
-                )
-                .unwrap(),
-            );
-            let outlet = MyOutlet(StreamOutlet::new(&info.0, 1, 360).unwrap());
-            loop {
-                select! {
-                    _ = rx.changed() => {
-                        break;
-                    }
-                    Some(b) = s.next() => {
-                        if b.uuid != DATA_CHAR {
-                        }
-                        outlet.0.push_sample(&[b.value.as_slice()]).unwrap();
-                    }
+        let mut text = self.name().to_string();
+        if let Some(role) = self.role {
+            text += &format!(" ({role})");
+        }
+        text += &format!(" - {:?} - {}", self.state, self.info.summary());
+        if let Some(rssi) = self.quality.last_rssi() {
+            text += &format!(", {rssi} dBm");
+        }
+        if let Some(session) = &self.session {
+            text += &format!(", loss {:.1}%", session.stats().loss_rate() * 100.0);
+        }
+        if let Some(reconnect) = &self.reconnect {
+            text += &format!(" - {}", reconnect.describe(&self.policy));
+        }
+        text
+    }
+
+    /// Labelled lines for the detail view.
+    fn detail(&self) -> String {
+        let mut lines = vec![match self.role {
+            Some(role) => format!("{} ({role})", self.name()),
+            None => self.name().to_string(),
+        }];
+        if self.alias.is_some() {
+            lines.push(format!("advertised as {}", self.name));
+        }
+        lines.push(format!("address {}", self.transport.address()));
+        lines.push(match (self.adapter(), self.state) {
+            (Some(adapter), Some(state)) => format!("connected via {adapter}, {state:?}"),
+            (Some(adapter), None) => format!("connected via {adapter}"),
+            (None, _) => "not connected".to_string(),
+        });
+        if let Some(reconnect) = &self.reconnect {
+            lines.push(reconnect.describe(&self.policy));
+        }
+        if let Some(rssi) = self.quality.last_rssi() {
+            lines.push(format!("signal {rssi} dBm"));
+        }
+        if let Some(side) = self.side {
+            lines.push(format!("{side:?} side of a subject"));
+        }
+        lines.extend(self.info.details());
+        if let Some(clock) = self.clock {
+            lines.push(format!(
+                "clock offset {:.3} s, within {:.1} ms",
+                clock.offset,
+                clock.rtt / 2.0 * 1e3
+            ));
+        } else if self.connected {
+            lines.push("no device clock".to_string());
+        }
+        lines.push(format!(
+            "{:?} at {} Hz, gaps {}, CSV {}",
+            self.config.mode,
+            self.config.frequency.hz(),
+            if self.fill_gaps { "filled" } else { "left out" },
+            if self.record_csv { "on" } else { "off" }
+        ));
+        if let Some(stats) = self.stream_stats() {
+            lines.push(format!(
+                "streaming {} samples, loss {:.1} %, {} resyncs, drift {:.0} ppm, jitter {:.1} ms",
+                stats.samples,
+                stats.loss_rate() * 100.0,
+                stats.resyncs,
+                stats.drift_ppm,
+                stats.jitter * 1e3
+            ));
+        }
+        if let Some(csv) = self.session.as_ref().and_then(|s| s.csv()) {
+            lines.push(format!("recording to {}", csv.display()));
+        }
+        if let Some(file) = self.files.get(self.selected_file) {
+            lines.push(format!(
+                "log file {} of {}: {:?} at {} Hz, {} bytes",
+                file.index + 1,
+                self.files.len(),
+                file.config.mode,
+                file.config.frequency.hz(),
+                file.size
+            ));
+        }
+        if let Some(readout) = &self.readout {
+            lines.push(match readout.status() {
+                ReadoutStatus::Running { received, size } => {
+                    format!(
+                        "reading out {} %",
+                        received as u64 * 100 / size.max(1) as u64
+                    )
+                }
+                ReadoutStatus::Verified(path) => format!("read out to {}", path.display()),
+                ReadoutStatus::Failed(e) => format!("readout failed: {e}"),
+            });
+        }
+        if let Some(status) = self.firmware_status() {
+            lines.push(match status {
+                FirmwareStatus::Checking => "firmware update: checking".to_string(),
+                FirmwareStatus::EnteringBootloader => {
+                    "firmware update: entering bootloader".to_string()
                 }
+                FirmwareStatus::Transferring { sent, size } => {
+                    format!("firmware update: transferring {sent} of {size} bytes")
+                }
+                FirmwareStatus::Verifying => "firmware update: verifying".to_string(),
+                FirmwareStatus::Rebooting => "firmware update: rebooting".to_string(),
+                FirmwareStatus::Done(version) => format!("firmware update to {version} done"),
+                FirmwareStatus::Failed(e) => format!("firmware update failed: {e}"),
+            });
+        }
+        lines.join("\n")
+    }
+
+    pub fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    /// The state as of the last query.
+    pub fn state(&self) -> Option<MitchState> {
+        self.state
+    }
+
+    /// Counters of the running stream, if any.
+    pub fn stream_stats(&self) -> Option<StreamStats> {
+        self.session.as_ref().map(|s| s.stats())
+    }
+
+    async fn send_command(&self, command: Command) -> color_eyre::Result<Response> {
+        send_command(self.transport.as_ref(), command).await
+    }
+
+    /// Takes over what the registry knows of the device.
+    pub fn apply(&mut self, entry: DeviceEntry) {
+        self.alias = entry.alias;
+        self.role = entry.role;
+        self.subject = entry.subject;
+        if let Some(config) = entry.config {
+            self.set_config(config);
+        }
+        if let Some(fill_gaps) = entry.fill_gaps {
+            self.fill_gaps = fill_gaps;
+        }
+        if let Some(csv) = entry.csv {
+            self.record_csv = csv;
+        }
+    }
+
+    /// The current settings as the registry keeps them.
+    pub fn entry(&self) -> DeviceEntry {
+        DeviceEntry {
+            alias: self.alias.clone(),
+            role: self.role,
+            subject: self.subject.clone(),
+            config: Some(self.config),
+            fill_gaps: Some(self.fill_gaps),
+            csv: Some(self.record_csv),
+        }
+    }
+
+    /// The stream configuration used by the next or current recording.
+    pub fn config(&self) -> StreamConfig {
+        self.config
+    }
+
+    /// Selects the stream configuration for the next recording.
+    ///
+    /// The configuration of a running stream is not affected.
+    pub fn set_config(&mut self, config: StreamConfig) {
+        if !self.is_streaming() {
+            self.config = config;
+        }
+    }
+
+    /// Switches between leaving out lost packets and replacing them by NaN samples, from the
+    /// next recording on.
+    pub fn toggle_gap_fill(&mut self) {
+        self.fill_gaps = !self.fill_gaps;
+    }
+
+    /// Switches recording to a CSV file next to the LSL outlet on or off, from the next
+    /// recording on.
+    pub fn toggle_csv(&mut self) {
+        self.record_csv = !self.record_csv;
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.session.is_some() || self.state == Some(MitchState::SysTx)
+    }
+
+    /// Starts streaming with `config` and publishes the data on a new LSL outlet.
+    ///
+    /// Does nothing if a stream session is already running.
+    pub(crate) async fn start_recording(&mut self, config: StreamConfig) -> color_eyre::Result<()> {
+        if self.session.is_some() {
+            return Ok(());
+        }
+        let session = self.prepare_recording(config).await?;
+        self.start_prepared(session).await?;
+        Ok(())
+    }
+
+    /// Starts recording together with the other mitches of `group`, see [`group`](super::group).
+    pub(crate) async fn start_recording_in(
+        &mut self,
+        mut group: GroupStart,
+    ) -> color_eyre::Result<()> {
+        if self.session.is_some() {
+            group.report(self.name(), GroupReport::Failed);
+            return Err(eyre!("{} is streaming already", self.name()));
+        }
+        let session = match self.prepare_recording(self.config).await {
+            Ok(session) => session,
+            Err(e) => {
+                group.report(self.name(), GroupReport::Failed);
+                return Err(e);
             }
-        });
-        return Ok(tx);
+        };
+        group.report(self.name(), GroupReport::Ready);
+        if !group.go().await {
+            // The group reports why it was given up
+            return session.stop().await;
+        }
+        match self.start_prepared(session).await {
+            Ok(time) => {
+                group.report(self.name(), GroupReport::Started { time });
+                Ok(())
+            }
+            Err(e) => {
+                group.report(self.name(), GroupReport::Failed);
+                Err(e)
+            }
+        }
     }
 
-    pub(crate) async fn start_recording(&mut self) -> color_eyre::Result<()> {
-        if self.state.map(|s| s == MitchState::SysTx).unwrap_or(false) {
+    /// Subscribes to the data and opens the outlet, and the CSV file if enabled, for recording
+    /// with `config`.
+    async fn prepare_recording(
+        &mut self,
+        config: StreamConfig,
+    ) -> color_eyre::Result<StreamSession> {
+        self.config = config;
+        self.transport.subscribe(DATA_CHAR).await?;
+        let notifications = self.transport.notifications().await?;
+        let csv = if self.record_csv {
+            let dir = Path::new(RECORDING_DIR);
+            Some(CsvRecorder::create(dir, self.name(), config).await?)
+        } else {
+            None
+        };
+        let sinks = Sinks {
+            pair: self.side.zip(self.combined.clone()),
+            csv,
+            xdf: self.xdf.clone(),
+        };
+        StreamSession::start(
+            self.name(),
+            &self.transport.address(),
+            &self.info,
+            config,
+            self.fill_gaps,
+            sinks,
+            notifications,
+        )
+    }
+
+    /// Starts the stream of the prepared `session` on the device.
+    ///
+    /// Returns the LSL time of the first sample, or the middle of the start command exchange
+    /// if the device does not tell.
+    async fn start_prepared(&mut self, mut session: StreamSession) -> color_eyre::Result<f64> {
+        let sent = lsl::local_clock();
+        let response = match self.send_command(Command::StartStream(self.config)).await {
+            Ok(response) => response,
+            Err(e) => {
+                // The failed start is the error to report
+                let _ = session.stop().await;
+                return Err(e);
+            }
+        };
+        let answered = lsl::local_clock();
+        // Firmware without a clock does not report the time of the first sample
+        let device_start = response.time().ok();
+        let first_sample = device_start
+            .zip(self.clock)
+            .map(|(time, clock)| clock.to_lsl(time));
+        session.anchor(first_sample, device_start);
+        self.session = Some(session);
+        Ok(first_sample.unwrap_or((sent + answered) / 2.0))
+    }
+
+    /// Stops streaming on the device and tears down the LSL outlet.
+    ///
+    /// The outlet is closed and the recording completed even if the device could not be
+    /// reached. A device that is not streaming is left alone, logging only stops with
+    /// [`Mitch::stop_logging`].
+    pub(crate) async fn stop_recording(&mut self) -> color_eyre::Result<()> {
+        if !self.is_streaming() {
             return Ok(());
         }
-        let c = self.per.characteristics();
-        let data_char = c.iter().find(|c| c.uuid == DATA_CHAR).unwrap();
-        self.per.subscribe(data_char).await?;
-        let cmd_char = c.iter().find(|c| c.uuid == COMMAND_CHAR).unwrap();
-        self.per
-            .write(
-                cmd_char,
-                Commands::StartStream.as_ref(),
-                WriteType::WithResponse,
-            )
+        let result = self
+            .send_command(Command::SetState(MitchState::SysIdle))
+            .await;
+        if let Some(session) = self.session.take() {
+            session.stop().await?;
+        }
+        result.map(|_| ())
+    }
+
+    pub fn is_logging(&self) -> bool {
+        self.state == Some(MitchState::SysLog)
+    }
+
+    /// Starts logging with `config` to the memory of the device.
+    ///
+    /// Logging continues after the device is disconnected, [`Mitch::connect`] picks up the
+    /// state again.
+    pub(crate) async fn start_logging(&mut self, config: StreamConfig) -> color_eyre::Result<()> {
+        if self.is_logging() {
+            return Ok(());
+        }
+        if self.is_streaming() {
+            return Err(eyre!(
+                "{} is streaming, stop it before logging",
+                self.name()
+            ));
+        }
+        self.config = config;
+        self.send_command(Command::StartLog(config)).await?;
+        self.state = Some(MitchState::SysLog);
+        Ok(())
+    }
+
+    pub(crate) async fn stop_logging(&mut self) -> color_eyre::Result<()> {
+        if !self.is_logging() {
+            return Ok(());
+        }
+        self.send_command(Command::SetState(MitchState::SysIdle))
             .await?;
-        self.per.read(cmd_char).await?;
-        self.start_lsl_stream().await?;
+        self.state = Some(MitchState::SysIdle);
         Ok(())
     }
 
-    pub(crate) async fn stop_recording(&mut self) -> color_eyre::Result<()> {
-        let characteristics = self.per.characteristics();
-        let cmd_char = characteristics
+    /// Queries the log files stored on the device.
+    pub(crate) async fn list_files(&mut self) -> color_eyre::Result<()> {
+        let count = self
+            .send_command(Command::GetFileCount)
+            .await?
+            .file_count()?;
+        let mut files = Vec::with_capacity(count as usize);
+        for index in 0..count {
+            let info = self.send_command(Command::GetFileInfo(index)).await?;
+            files.push(LogFile::parse(index, &info)?);
+        }
+        self.files = files;
+        self.selected_file = 0;
+        self.verified.clear();
+        Ok(())
+    }
+
+    pub fn select_next_file(&mut self) {
+        self.selected_file = min(self.selected_file + 1, self.files.len().saturating_sub(1));
+    }
+
+    pub fn select_prev_file(&mut self) {
+        self.selected_file = self.selected_file.saturating_sub(1);
+    }
+
+    /// Starts downloading the selected log file into [`READOUT_DIR`].
+    pub(crate) fn start_readout(&mut self) -> color_eyre::Result<()> {
+        if self.readout.as_ref().is_some_and(|r| !r.is_finished()) {
+            return Ok(());
+        }
+        if !self.connected || self.is_streaming() || self.is_logging() {
+            return Err(eyre!(
+                "{} must be connected and idle for readout",
+                self.name()
+            ));
+        }
+        let file = *self
+            .files
+            .get(self.selected_file)
+            .ok_or_else(|| eyre!("No log file selected on {}", self.name()))?;
+        // Files are named after the device, an alias may change between sessions
+        self.readout = Some(ReadoutSession::start(
+            self.transport.clone(),
+            &self.name,
+            file,
+            self.clock,
+            Path::new(READOUT_DIR),
+        ));
+        Ok(())
+    }
+
+    /// Records the result of a finished readout.
+    fn poll_readout(&mut self) {
+        let Some(readout) = &self.readout else {
+            return;
+        };
+        let index = readout.file().index;
+        if matches!(readout.status(), ReadoutStatus::Verified(_)) && !self.verified.contains(&index)
+        {
+            self.verified.push(index);
+        }
+    }
+
+    /// Erases the memory of the device.
+    ///
+    /// Refuses to do so unless every listed log file was downloaded and verified.
+    pub(crate) async fn erase_memory(&mut self) -> color_eyre::Result<()> {
+        if let Some(file) = self
+            .files
             .iter()
-            .find(|c| c.uuid == COMMAND_CHAR)
-            .unwrap();
-        self.per
-            .write(
-                cmd_char,
-                Commands::StopStream.as_ref(),
-                WriteType::WithResponse,
-            )
-            .await?;
-        self.per.read(cmd_char).await?;
+            .find(|f| !self.verified.contains(&f.index))
+        {
+            return Err(eyre!(
+                "Log file {} of {} has not been downloaded",
+                file.index,
+                self.name()
+            ));
+        }
+        self.send_command(Command::EraseMemory).await?;
+        self.files.clear();
+        self.verified.clear();
+        self.selected_file = 0;
         Ok(())
     }
 
+    /// Starts updating the firmware of the connected device to `image`, which may be older than
+    /// the current one if `allow_downgrade`.
+    pub fn start_firmware_update(
+        &mut self,
+        image: Arc<FirmwareImage>,
+        allow_downgrade: bool,
+    ) -> color_eyre::Result<()> {
+        if self.is_updating_firmware() {
+            return Ok(());
+        }
+        if !self.connected || self.is_streaming() || self.is_logging() {
+            return Err(eyre!(
+                "{} must be connected and idle for a firmware update",
+                self.name()
+            ));
+        }
+        self.readout = None;
+        self.firmware_result = None;
+        self.firmware = Some(FirmwareUpdate::start(
+            self.transport.clone(),
+            image,
+            allow_downgrade,
+        ));
+        Ok(())
+    }
+
+    /// Progress of the running firmware update, or how the last one ended.
+    pub fn firmware_status(&self) -> Option<FirmwareStatus> {
+        self.firmware
+            .as_ref()
+            .map(|f| f.status())
+            .or_else(|| self.firmware_result.clone())
+    }
+
+    /// Whether a firmware update owns the link to the device.
+    pub fn is_updating_firmware(&self) -> bool {
+        self.firmware_status().is_some_and(|s| !s.is_finished())
+    }
+
+    /// Takes over the link state a finished firmware update left behind, once.
+    async fn poll_firmware(&mut self) {
+        let Some(status) = self
+            .firmware
+            .as_ref()
+            .map(|f| f.status())
+            .filter(FirmwareStatus::is_finished)
+        else {
+            return;
+        };
+        self.firmware = None;
+        match &status {
+            FirmwareStatus::Failed(_) => {
+                // The update dropped the link already, the device may be stuck in the bootloader
+                let _ = self.transport.disconnect().await;
+                self.detach();
+                self.state = None;
+            }
+            FirmwareStatus::Done(version) => {
+                self.info.firmware = Some(*version);
+                self.info.forget_rejected();
+            }
+            _ => {}
+        }
+        self.firmware_result = Some(status);
+    }
+
     pub(crate) async fn update_state(&mut self) -> color_eyre::Result<()> {
         if !self.connected {
             self.state = None;
             return Ok(());
         }
-        let c = self.per.characteristics();
-        let cmd_char = c.iter().find(|c| c.uuid == COMMAND_CHAR).unwrap();
-        self.per
-            .write(
-                cmd_char,
-                Commands::GetState.as_ref(),
-                WriteType::WithResponse,
-            )
-            .await?;
-        let state = MitchState::try_from(self.per.read(cmd_char).await?[4])?;
+        let state = self.send_command(Command::GetState).await?.state()?;
         self.state = Some(state);
         Ok(())
     }
 
+    /// Sets the device clock to the host time unless that would disturb a running recording,
+    /// then estimates its offset to the LSL clock.
+    ///
+    /// The time command is unconfirmed, so only devices known to understand it are set. Only
+    /// fails if the device cannot be reached, firmware without a clock leaves the offset
+    /// unknown.
+    pub(crate) async fn sync_clock(&mut self) -> color_eyre::Result<()> {
+        if !self.connected {
+            return Err(eyre!("{} is not connected", self.name()));
+        }
+        if self.transport.answers_unconfirmed() && !self.is_streaming() && !self.is_logging() {
+            clock::set_device_clock(self.transport.as_ref()).await?;
+        }
+        self.clock = ClockSync::estimate(self.transport.as_ref()).await?;
+        Ok(())
+    }
+
+    /// Queries battery charge, memory and the device details not known yet.
+    ///
+    /// Only fails if the device cannot be reached, rejected queries are noted.
+    pub(crate) async fn refresh_info(&mut self) -> color_eyre::Result<()> {
+        if !self.connected {
+            return Ok(());
+        }
+        let notes = self.info.refresh(self.transport.as_ref()).await?;
+        self.notes.extend(notes);
+        self.info_read = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Connects to the device and restarts the stream that was running when the link was lost.
     pub(crate) async fn connect(&mut self) -> color_eyre::Result<()> {
         if self.connected {
             return Ok(());
         }
-        self.per.connect().await?;
-        self.per.discover_services().await?;
+        if let Err(e) = self.setup_link().await {
+            let _ = self.transport.disconnect().await;
+            self.detach();
+            return Err(e);
+        }
+        self.reconnect = None;
+        Ok(())
+    }
+
+    async fn setup_link(&mut self) -> color_eyre::Result<()> {
+        self.transport = self.pick_route();
+        self.slot = Some(self.load.attach(&self.transport.adapter()));
+        self.transport.connect().await?;
+        self.transport.discover().await?;
         self.connected = true;
+        // The device may still be logging from an earlier connection
+        self.update_state().await?;
+        // Clock and details rely on unconfirmed commands, the link is of use without them
+        if let Err(e) = self.sync_clock().await {
+            self.notes.push(format!("Clock sync failed: {e}"));
+        }
+        if let Err(e) = self.refresh_info().await {
+            self.notes
+                .push(format!("Reading the device details failed: {e}"));
+        }
+        if let Some(config) = self.resume {
+            self.start_recording(config).await?;
+            self.resume = None;
+        }
+        Ok(())
+    }
+
+    /// Tears down everything depending on the link and schedules reconnection attempts.
+    async fn link_lost(&mut self, error: String) {
+        self.resume = self.resume.or(self.session.as_ref().map(|s| s.config()));
+        if let Some(session) = self.session.take() {
+            // Reporting the lost link matters more than a failed recording
+            let _ = session.stop().await;
+        }
+        self.readout = None;
+        // The link is most likely gone already
+        let _ = self.transport.disconnect().await;
+        self.detach();
+        self.state = None;
+        self.reconnect = Some(Reconnect::start(&self.policy, error));
+    }
+
+    /// Makes the next reconnection attempt once its delay elapsed.
+    ///
+    /// Fails once the last attempt failed.
+    async fn poll_reconnect(&mut self) -> color_eyre::Result<()> {
+        let Some(Reconnect::Waiting { attempt, next, .. }) = self.reconnect else {
+            return Ok(());
+        };
+        if Instant::now() < next {
+            return Ok(());
+        }
+        if let Err(e) = self.connect().await {
+            let reconnect = Reconnect::failed(&self.policy, attempt, e.to_string());
+            let result = match reconnect {
+                Reconnect::GaveUp { .. } => Err(eyre!("{}", reconnect.describe(&self.policy))),
+                Reconnect::Waiting { .. } => Ok(()),
+            };
+            self.reconnect = Some(reconnect);
+            return result;
+        }
         Ok(())
     }
 
+    /// Runs `command`, called by the task owning the mitch.
+    pub(crate) async fn execute(&mut self, command: MitchCommand) -> color_eyre::Result<()> {
+        match command {
+            MitchCommand::Connect => self.connect().await,
+            MitchCommand::Disconnect => self.disconnect().await,
+            MitchCommand::StartRecording => self.start_recording(self.config).await,
+            MitchCommand::StartRecordingIn(group) => self.start_recording_in(group).await,
+            MitchCommand::StopRecording => self.stop_recording().await,
+            MitchCommand::StartLogging => self.start_logging(self.config).await,
+            MitchCommand::StopLogging => self.stop_logging().await,
+            MitchCommand::ListFiles => self.list_files().await,
+            MitchCommand::PrevFile => {
+                self.select_prev_file();
+                Ok(())
+            }
+            MitchCommand::NextFile => {
+                self.select_next_file();
+                Ok(())
+            }
+            MitchCommand::Readout => self.start_readout(),
+            MitchCommand::EraseMemory => self.erase_memory().await,
+            MitchCommand::NextMode => {
+                let mut config = self.config;
+                config.mode = config.mode.next();
+                self.set_config(config);
+                Ok(())
+            }
+            MitchCommand::NextFrequency => {
+                let mut config = self.config;
+                config.frequency = config.frequency.next();
+                self.set_config(config);
+                Ok(())
+            }
+            MitchCommand::ToggleGapFill => {
+                self.toggle_gap_fill();
+                Ok(())
+            }
+            MitchCommand::ToggleCsv => {
+                self.toggle_csv();
+                Ok(())
+            }
+            MitchCommand::SyncClock => self.sync_clock().await,
+            MitchCommand::UpdateFirmware {
+                image,
+                allow_downgrade,
+            } => self.start_firmware_update(image, allow_downgrade),
+            MitchCommand::Advertised {
+                name,
+                rssi,
+                tx_power,
+            } => {
+                self.advertised(name, rssi, tx_power);
+                Ok(())
+            }
+            MitchCommand::Pair { side, combined } => {
+                self.side = side;
+                self.combined = combined.filter(|_| side.is_some());
+                Ok(())
+            }
+            MitchCommand::Register(entry) => {
+                self.apply(entry);
+                Ok(())
+            }
+            MitchCommand::AddRoute(transport) => {
+                self.add_route(transport);
+                Ok(())
+            }
+            MitchCommand::LinkUp(_) => {
+                self.last_seen = Instant::now();
+                Ok(())
+            }
+            MitchCommand::LinkDown(adapter) => self.link_down(&adapter).await,
+        }
+    }
+
+    /// Takes note of an advertisement of the device.
+    fn advertised(&mut self, name: Option<String>, rssi: Option<i16>, tx_power: Option<i16>) {
+        self.last_seen = Instant::now();
+        self.quality.signal(rssi, tx_power);
+        if let Some(name) = name.filter(|n| n.starts_with("mitch")) {
+            self.name = name;
+        }
+    }
+
+    /// Starts reconnecting right away when `adapter` reports the link as gone, instead of
+    /// waiting for the next poll to fail.
+    async fn link_down(&mut self, adapter: &str) -> color_eyre::Result<()> {
+        // Only the adapter carrying the connection can end it
+        if self.adapter() != Some(adapter) {
+            return Ok(());
+        }
+        // The update reboots the device, which ends the link on purpose
+        if !self.connected || self.is_updating_firmware() {
+            return Ok(());
+        }
+        self.link_lost("Disconnected by the adapter".to_string())
+            .await;
+        Err(eyre!("Link lost: disconnected by the adapter"))
+    }
+
+    /// Polls the running sessions and the device, reconnecting if the link was lost.
+    ///
+    /// Fails when the link is lost, when reconnecting is given up and when the device answers
+    /// the state query with an invalid reply. The latter keeps the link and the stream.
+    pub(crate) async fn poll(&mut self) -> color_eyre::Result<()> {
+        self.poll_readout();
+        self.poll_firmware().await;
+        // The update reboots the device, polling in between would only disturb it
+        if self.is_updating_firmware() {
+            return Ok(());
+        }
+        self.poll_reconnect().await?;
+        let sent = Instant::now();
+        let polled = match self.update_state().await {
+            Ok(()) => {
+                // The state query is a single round trip to the device
+                if self.connected {
+                    self.quality.latency(sent.elapsed());
+                }
+                if self.info_read.is_none_or(|t| t.elapsed() >= INFO_INTERVAL) {
+                    self.refresh_info().await
+                } else {
+                    Ok(())
+                }
+            }
+            Err(e) => Err(e),
+        };
+        // A device that replies at all is still in reach, only a failed exchange loses the link
+        if let Err(e) = &polled
+            && e.downcast_ref::<ProtocolError>().is_none()
+        {
+            self.link_lost(e.to_string()).await;
+            return Err(eyre!("Link lost: {e}"));
+        }
+        self.poll_quality().await;
+        polled
+    }
+
+    /// Measures the throughput of the stream and, while connected, the signal strength as the
+    /// adapter reports it.
+    async fn poll_quality(&mut self) {
+        self.quality.count(self.stream_stats().map(|s| s.bytes));
+        if !self.connected {
+            return;
+        }
+        // Not every adapter reports the signal strength of a connected device
+        if let Ok(rssi) = self.transport.read_rssi().await {
+            self.quality.signal(rssi, None);
+        }
+    }
+
+    /// The current state for the UI, with the error of the last failed command.
+    pub fn snapshot(&self, last_error: Option<String>) -> MitchSnapshot {
+        MitchSnapshot {
+            id: self.id(),
+            name: self.name().to_string(),
+            entry: self.entry(),
+            summary: self.name_with_state(),
+            detail: self.detail(),
+            connected: self.connected,
+            adapter: self.adapter().map(str::to_string),
+            side: self.side,
+            needs_charging: self.info.needs_charging(),
+            quality: self.quality.clone(),
+            last_seen: self.last_seen,
+            firmware: self.firmware_status(),
+            last_error,
+        }
+    }
+
+    /// Disconnects on request, which also ends any reconnection attempts.
     pub(crate) async fn disconnect(&mut self) -> color_eyre::Result<()> {
+        self.reconnect = None;
+        self.resume = None;
         if !self.connected {
             return Ok(());
         }
-        self.per.disconnect().await?;
+        let stopped = match self.session.take() {
+            Some(session) => session.stop().await,
+            None => Ok(()),
+        };
+        // An interrupted readout resumes from its partial file on the next attempt
+        self.readout = None;
+        self.transport.disconnect().await?;
+        self.detach();
+        stopped
+    }
+
+    /// Marks the link as gone, which frees its place on the adapter.
+    fn detach(&mut self) {
         self.connected = false;
-        Ok(())
+        self.slot = None;
+    }
+
+    /// The route through the adapter with the fewest connections, preferring the last one used.
+    fn pick_route(&self) -> Arc<dyn MitchTransport> {
+        let current = self.transport.adapter();
+        self.routes
+            .iter()
+            .min_by_key(|r| {
+                let adapter = r.adapter();
+                (self.load.connections(&adapter), adapter != current)
+            })
+            .unwrap_or(&self.transport)
+            .clone()
+    }
+
+    /// Adds `transport` as route to the device, unless its adapter has one already.
+    fn add_route(&mut self, transport: Arc<dyn MitchTransport>) {
+        let adapter = transport.adapter();
+        if !self.routes.iter().any(|r| r.adapter() == adapter) {
+            self.routes.push(transport);
+        }
+    }
+
+    /// Failures noted since the last call, which need no attention but belong in the log.
+    pub(crate) fn take_notes(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.notes)
+    }
+
+    /// Counts the connections of this mitch in `load`, which is shared with other mitches.
+    pub fn share_load(&mut self, load: AdapterLoad) {
+        self.load = load;
+    }
+
+    /// Records the streams of this mitch with `xdf`, which is shared with the other mitches.
+    pub fn share_xdf(&mut self, xdf: XdfRecorder) {
+        self.xdf = xdf;
+    }
+
+    /// Name of the adapter carrying the connection, if connected.
+    pub fn adapter(&self) -> Option<&str> {
+        self.slot.as_ref().map(|s| s.adapter())
+    }
+
+    pub(crate) fn transport(&self) -> Arc<dyn MitchTransport> {
+        self.transport.clone()
     }
 }
 
-impl WidgetRef for Mitch {
+/// Writes `command` to the command characteristic and returns the validated reply.
+pub(crate) async fn send_command(
+    transport: &dyn MitchTransport,
+    command: Command,
+) -> color_eyre::Result<Response> {
+    // Readouts and firmware updates exchange commands next to the task of the mitch
+    let _exchange = transport.exchange_lock().lock().await;
+    transport.write(COMMAND_CHAR, &command.encode()?).await?;
+    let reply = transport.read(COMMAND_CHAR).await?;
+    Ok(Response::parse(&command, &reply)?)
+}
+
+impl WidgetRef for MitchSnapshot {
     fn render_ref(&self, area: Rect, buf: &mut Buffer) {
-        let t = format!("{:#?}", self);
+        let mut t = self.detail.clone();
+        if let Some(error) = &self.last_error {
+            t += &format!("\nlast error: {error}");
+        }
         let (h, l) = t
             .lines()
             .fold((2, 0), |acc, l| (acc.0 + 1, max(acc.1, l.len())));
@@ -253,13 +1085,39 @@ impl WidgetRef for Mitch {
             .block(block);
 
         paragraph.render(a, buf);
+
+        if let Some(FirmwareStatus::Transferring { sent, size }) = self.firmware {
+            let below = Rect {
+                y: min(a.bottom(), area.bottom().saturating_sub(3)),
+                height: min(3, area.height),
+                ..a
+            };
+            Gauge::default()
+                .block(Block::default().borders(Borders::ALL).title("Firmware"))
+                .gauge_style(Style::default().fg(Color::Cyan))
+                .ratio(sent as f64 / max(size, 1) as f64)
+                .render(below, buf);
+        }
     }
 }
 
-#[derive(Clone, Debug)]
+/// The discovered mitches, each running in its own task.
+#[derive(Debug)]
 pub struct MitchList {
-    inner: Vec<Mitch>,
+    inner: Vec<MitchHandle>,
     pub active: usize,
+    /// Time without advertisements after which a disconnected mitch is marked stale.
+    pub stale_after: Duration,
+    /// Connections per adapter of all mitches in the list.
+    pub load: AdapterLoad,
+    /// Recording of the streams of all mitches in the list.
+    pub xdf: XdfRecorder,
+    /// Ids of the mitches selected for group start and stop.
+    selected: HashSet<String>,
+    /// Pairs of insoles worn by the same person.
+    subjects: Vec<Subject>,
+    /// Number of subjects paired so far, for naming new ones.
+    subject_count: usize,
 }
 
 impl Default for MitchList {
@@ -273,34 +1131,259 @@ impl MitchList {
         Self {
             inner: Vec::new(),
             active: 0,
+            stale_after: STALE_AFTER,
+            load: AdapterLoad::default(),
+            xdf: XdfRecorder::default(),
+            selected: HashSet::new(),
+            subjects: Vec::new(),
+            subject_count: 0,
         }
     }
 
-    pub fn insert(&mut self, mitch: Mitch) {
-        self.inner.push(mitch);
+    /// Ends the tasks of all mitches and waits until they stopped recording and disconnected.
+    pub async fn close(&mut self) {
+        futures::future::join_all(self.inner.drain(..).map(MitchHandle::close)).await;
     }
 
-    pub fn get_active(&self) -> &Mitch {
-        &self.inner[self.active]
+    /// Adds `mitch` and spawns the task controlling it, which reports errors to `events`.
+    ///
+    /// A mitch that is already in the list is not added again, only its route is.
+    pub fn insert(&mut self, mut mitch: Mitch, events: mpsc::UnboundedSender<Event>) {
+        if self.inner.iter().any(|m| m.id() == mitch.id()) {
+            self.add_route(mitch.transport());
+            return;
+        }
+        let id = mitch.id();
+        let entry = mitch.entry();
+        mitch.share_load(self.load.clone());
+        mitch.share_xdf(self.xdf.clone());
+        self.inner.push(MitchHandle::spawn(mitch, events));
+        self.pair_registered(&id, &entry);
     }
 
-    pub fn get_active_mut(&mut self) -> &mut Mitch {
-        &mut self.inner[self.active]
+    /// Pairs the mitch with `id` and the other insole registered to the same subject, once
+    /// both are in the list.
+    fn pair_registered(&mut self, id: &str, entry: &DeviceEntry) {
+        let (Some(name), Some(side)) = (&entry.subject, entry.role.and_then(|r| r.side())) else {
+            return;
+        };
+        if self.subjects.iter().any(|s| &s.name == name) {
+            return;
+        }
+        let partner = self.inner.iter().find(|m| {
+            let other = m.snapshot().entry;
+            other.subject.as_ref() == Some(name)
+                && other.role.and_then(|r| r.side()).is_some_and(|s| s != side)
+                && self.subject_of(m.id()).is_none()
+        });
+        let Some(partner) = partner.map(|m| m.id().to_string()) else {
+            return;
+        };
+        let (left, right) = match side {
+            Side::Left => (id.to_string(), partner),
+            Side::Right => (partner, id.to_string()),
+        };
+        self.subjects.push(Subject {
+            name: name.clone(),
+            left,
+            right,
+            combined: None,
+        });
+        self.update_subject(self.subjects.len() - 1);
     }
 
-    // Update state for all mitches if the update fails we disconnect the mitch
-    // TODO: maybe we should mark it
-    pub async fn update(&mut self) -> color_eyre::Result<()> {
-        for i in (0..self.inner.len()).rev() {
-            if self.inner[i].update_state().await.is_err() {
-                // we ignore the error here since it is very likely that the connection has gone
-                // away and therefore the function would error and that is fine
-                let _ = self.inner[i].disconnect().await;
-            }
+    /// Adds `transport` as further route to the mitch it reaches, if it is in the list.
+    pub fn add_route(&self, transport: Arc<dyn MitchTransport>) {
+        let id = transport.id();
+        self.send_to(&id, MitchCommand::AddRoute(transport));
+    }
+
+    /// Sends `command` to the mitch with `id`, if it is in the list.
+    pub fn send_to(&self, id: &str, command: MitchCommand) {
+        if let Some(mitch) = self.inner.iter().find(|m| m.id() == id) {
+            mitch.send(command);
         }
+    }
+
+    /// Whether the mitch was neither connected nor heard of for a while.
+    pub fn is_stale(&self, mitch: &MitchSnapshot) -> bool {
+        !mitch.connected && mitch.last_seen.elapsed() > self.stale_after
+    }
+
+    /// Selects the active mitch for group start and stop, or unselects it.
+    pub fn toggle_selected(&mut self) {
+        if let Some(mitch) = self.inner.get(self.active)
+            && !self.selected.remove(mitch.id())
+        {
+            self.selected.insert(mitch.id().to_string());
+        }
+    }
+
+    pub fn is_selected(&self, mitch: &MitchHandle) -> bool {
+        self.selected.contains(mitch.id())
+    }
+
+    /// The mitches selected for group start and stop.
+    pub fn selected(&self) -> impl Iterator<Item = &MitchHandle> {
+        self.inner.iter().filter(|m| self.is_selected(m))
+    }
+
+    /// Like [`MitchList::selected`], together with the partners of the paired ones.
+    pub fn selected_with_partners(&self) -> Vec<&MitchHandle> {
+        self.inner
+            .iter()
+            .filter(|m| {
+                self.is_selected(m)
+                    || self
+                        .subject_of(m.id())
+                        .and_then(|s| s.partner(m.id()))
+                        .is_some_and(|p| self.selected.contains(p))
+            })
+            .collect()
+    }
+
+    /// The subject the mitch with `id` belongs to.
+    pub fn subject_of(&self, id: &str) -> Option<&Subject> {
+        self.subjects.iter().find(|s| s.side(id).is_some())
+    }
+
+    /// The active mitch and, if it is paired, its partner.
+    pub fn active_with_partner(&self) -> Vec<&MitchHandle> {
+        let Some(active) = self.get_active() else {
+            return Vec::new();
+        };
+        let partner = self
+            .subject_of(active.id())
+            .and_then(|s| s.partner(active.id()))
+            .and_then(|id| self.inner.iter().find(|m| m.id() == id));
+        std::iter::once(active).chain(partner).collect()
+    }
+
+    /// Pairs the two selected mitches into a subject, the upper one in the list as left side.
+    pub fn pair_selected(&mut self) -> color_eyre::Result<()> {
+        let [left, right] = self.selected().collect::<Vec<_>>()[..] else {
+            return Err(eyre!(
+                "Select the two insoles of a subject with `Space` to pair them"
+            ));
+        };
+        if let Some(paired) = [left, right]
+            .into_iter()
+            .find(|m| self.subject_of(m.id()).is_some())
+        {
+            return Err(eyre!("{} is paired already", paired.snapshot().name));
+        }
+        let (left, right) = (left.id().to_string(), right.id().to_string());
+        // Subjects from the registry keep their names
+        let name = loop {
+            self.subject_count += 1;
+            let name = format!("subject-{}", self.subject_count);
+            if !self.subjects.iter().any(|s| s.name == name) {
+                break name;
+            }
+        };
+        self.subjects.push(Subject {
+            name,
+            left,
+            right,
+            combined: None,
+        });
+        self.update_subject(self.subjects.len() - 1);
         Ok(())
     }
 
+    /// Dissolves the subject of the active mitch.
+    pub fn unpair_active(&mut self) {
+        let Some(active) = self.get_active().map(|m| m.id().to_string()) else {
+            return;
+        };
+        let Some(index) = self.subjects.iter().position(|s| s.side(&active).is_some()) else {
+            return;
+        };
+        let subject = self.subjects.remove(index);
+        for id in [subject.left, subject.right] {
+            self.send_to(
+                &id,
+                MitchCommand::Pair {
+                    side: None,
+                    combined: None,
+                },
+            );
+        }
+    }
+
+    /// Swaps the sides of the subject of the active mitch.
+    pub fn swap_sides(&mut self) {
+        if let Some(index) = self.active_subject() {
+            let subject = &mut self.subjects[index];
+            std::mem::swap(&mut subject.left, &mut subject.right);
+            self.update_subject(index);
+        }
+    }
+
+    /// Switches the combined stream of the subject of the active mitch on or off, from the next
+    /// recording on.
+    pub fn toggle_combined(&mut self) {
+        if let Some(index) = self.active_subject() {
+            let subject = &mut self.subjects[index];
+            subject.combined = match subject.combined {
+                Some(_) => None,
+                None => Some(Arc::new(PairStream::new(
+                    subject.name.clone(),
+                    self.xdf.clone(),
+                ))),
+            };
+            self.update_subject(index);
+        }
+    }
+
+    fn active_subject(&self) -> Option<usize> {
+        let active = self.get_active()?.id();
+        self.subjects.iter().position(|s| s.side(active).is_some())
+    }
+
+    /// Tells both mitches of the subject at `index` their side and moves the right one below
+    /// the left one, keeping the active mitch.
+    fn update_subject(&mut self, index: usize) {
+        let subject = self.subjects[index].clone();
+        for (id, side) in [(&subject.left, Side::Left), (&subject.right, Side::Right)] {
+            self.send_to(
+                id,
+                MitchCommand::Pair {
+                    side: Some(side),
+                    combined: subject.combined.clone(),
+                },
+            );
+        }
+        let active = self.get_active().map(|m| m.id().to_string());
+        if let Some(right) = self.inner.iter().position(|m| m.id() == subject.right) {
+            let mitch = self.inner.remove(right);
+            let left = self.inner.iter().position(|m| m.id() == subject.left);
+            self.inner.insert(left.map_or(right, |l| l + 1), mitch);
+        }
+        if let Some(active) = active {
+            self.active = self
+                .inner
+                .iter()
+                .position(|m| m.id() == active)
+                .unwrap_or(self.active);
+        }
+    }
+
+    pub fn get_active(&self) -> Option<&MitchHandle> {
+        self.inner.get(self.active)
+    }
+
+    /// Sends `command` to the active mitch, if there is one.
+    pub fn send_active(&self, command: MitchCommand) {
+        if let Some(mitch) = self.inner.get(self.active) {
+            mitch.send(command);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MitchHandle> {
+        self.inner.iter()
+    }
+
     pub fn len(&self) -> usize {
         self.inner.len()
     }
@@ -312,19 +1395,31 @@ impl MitchList {
 
 impl WidgetRef for MitchList {
     fn render_ref(&self, area: Rect, buf: &mut Buffer) {
-        let info: Vec<String> = self.inner.iter().map(|m| m.name_with_state()).collect();
-        let len = info.iter().map(|m| m.len()).max().unwrap_or(0);
+        let snapshots: Vec<MitchSnapshot> = self.inner.iter().map(|m| m.snapshot()).collect();
+        let lines: Vec<String> = snapshots
+            .iter()
+            .map(|m| {
+                if self.is_stale(m) {
+                    format!("{} - stale", m.summary)
+                } else {
+                    m.summary.clone()
+                }
+            })
+            .collect();
+        // Selection mark and signal bars go in front of every line
+        let len = lines.iter().map(|l| l.len()).max().unwrap_or(0) + 7;
+        let rows = self.rows();
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(1);
         // Define a layout for the list items. Each item gets 3 rows.
         let item_height = 3;
-        let constraints: Vec<Constraint> = self
-            .inner
+        let constraints: Vec<Constraint> = rows
             .iter()
             .map(|_| Constraint::Length(item_height as u16))
             .collect();
         let a = center(
             area,
-            Constraint::Length((len + 5) as u16),
-            Constraint::Length((item_height * self.len()) as u16),
+            Constraint::Length((columns * (len + 5)) as u16),
+            Constraint::Length((item_height * rows.len()) as u16),
         );
 
         let chunks = Layout::default()
@@ -332,33 +1427,87 @@ impl WidgetRef for MitchList {
             .constraints(constraints)
             .split(a);
 
-        // Iterate over items and their corresponding chunks
-        for (i, _) in self.inner.iter().enumerate() {
-            // Determine the style of the block's border
-            let border_style = if i == self.active {
-                Style::default().fg(Color::Cyan) // Highlighted border
-            } else {
-                Style::default().fg(Color::DarkGray) // Normal border
-            };
+        // Iterate over rows and their corresponding chunks, a pair shares its row
+        for (row, chunk) in rows.iter().zip(chunks.iter()) {
+            let cells = Layout::horizontal(vec![Constraint::Fill(1); row.len()]).split(*chunk);
+            for (&i, cell) in row.iter().zip(cells.iter()) {
+                self.render_item(i, &snapshots[i], &lines[i], *cell, buf);
+            }
+        }
+    }
+}
 
-            let block = Block::default().borders(Borders::ALL).style(border_style);
+impl MitchList {
+    /// Groups the mitches into rows of a single mitch or a pair, left side first.
+    fn rows(&self) -> Vec<Vec<usize>> {
+        let mut rows: Vec<Vec<usize>> = Vec::new();
+        for (i, mitch) in self.inner.iter().enumerate() {
+            if rows.iter().flatten().any(|&j| j == i) {
+                continue;
+            }
+            let partner = self
+                .subject_of(mitch.id())
+                .and_then(|s| Some((s.side(mitch.id())?, s.partner(mitch.id())?)))
+                .and_then(|(side, id)| {
+                    let j = self.inner.iter().position(|m| m.id() == id)?;
+                    Some((side, j))
+                });
+            rows.push(match partner {
+                Some((Side::Left, j)) => vec![i, j],
+                Some((Side::Right, j)) => vec![j, i],
+                None => vec![i],
+            });
+        }
+        rows
+    }
 
-            let paragraph = Paragraph::new(info[i].as_str())
-                .style(Style::default().fg(Color::White))
-                .centered()
-                .block(block); // Center the text inside the block
+    fn render_item(
+        &self,
+        i: usize,
+        mitch: &MitchSnapshot,
+        text: &str,
+        area: Rect,
+        buf: &mut Buffer,
+    ) {
+        // Determine the style of the block's border
+        let border_style = if i == self.active {
+            Style::default().fg(Color::Cyan) // Highlighted border
+        } else {
+            Style::default().fg(Color::DarkGray) // Normal border
+        };
 
-            // We render the paragraph and tell it to be contained within the block.
-            // The block is rendered into the chunk area we calculated.
-            paragraph.render(chunks[i], buf);
+        let mut block = Block::default().borders(Borders::ALL).style(border_style);
+        if let Some(subject) = self.subject_of(&mitch.id)
+            && let Some(side) = mitch.side
+        {
+            block = block.title(format!("{} {}", subject.name, side.letter()));
         }
-    }
-}
 
-fn center(area: Rect, horizontal: Constraint, vertical: Constraint) -> Rect {
-    let [area] = Layout::horizontal([horizontal])
-        .flex(Flex::SpaceAround)
-        .areas(area);
-    let [area] = Layout::vertical([vertical]).flex(Flex::Center).areas(area);
-    area
+        let text_color = if self.is_stale(mitch) {
+            Color::DarkGray
+        } else if mitch.needs_charging {
+            Color::Red
+        } else {
+            Color::White
+        };
+        let mark = if self.is_selected(&self.inner[i]) {
+            Span::styled("✓ ", Style::default().fg(Color::Yellow))
+        } else {
+            Span::raw("  ")
+        };
+        let line = Line::from(vec![
+            mark,
+            mitch.quality.bars_span(),
+            Span::raw(" "),
+            text.into(),
+        ]);
+        let paragraph = Paragraph::new(line)
+            .style(Style::default().fg(text_color))
+            .centered()
+            .block(block); // Center the text inside the block
+
+        // We render the paragraph and tell it to be contained within the block.
+        // The block is rendered into the chunk area we calculated.
+        paragraph.render(area, buf);
+    }
 }
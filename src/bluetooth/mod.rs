@@ -1,72 +1,308 @@
+pub mod actor;
+pub mod adapter;
+pub mod clock;
+pub mod firmware;
+pub mod group;
+pub mod info;
 pub mod mitch;
+pub mod outlet;
+pub mod protocol;
+pub mod quality;
+pub mod readout;
+pub mod reconnect;
+pub mod recorder;
+pub mod registry;
+pub mod sample;
+pub mod session;
+pub mod sim;
+pub mod subject;
+pub mod timing;
+pub mod transport;
 
+use adapter::{AdapterChoice, AdapterInfo, AdapterState};
 use btleplug::{
     api::{Central as _, CentralEvent, CentralState, Manager as _, Peripheral as _, ScanFilter},
-    platform::{Adapter, Manager},
+    platform::{Adapter, Manager, PeripheralId},
 };
-use futures::StreamExt as _;
+use futures::{Stream, StreamExt as _};
 use mitch::Mitch;
-use tokio::sync::mpsc;
+use registry::Registry;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{select, sync::mpsc, task::JoinSet};
+use transport::{BtleTransport, MitchTransport};
 
 use crate::event::Event;
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum BluetoothEvent {
-    Discovered(Mitch),
-    NotActive,
+    /// A mitch seen for the first time.
+    Discovered(Box<Mitch>),
+    /// A discovered mitch is also in range of another adapter, through this transport.
+    Reachable(Arc<dyn MitchTransport>),
+    /// An advertisement of the discovered mitch with `id`.
+    Updated {
+        id: String,
+        name: Option<String>,
+        rssi: Option<i16>,
+        tx_power: Option<i16>,
+    },
+    /// `adapter` reports a link to the discovered mitch with `id`.
+    Connected { id: String, adapter: String },
+    /// `adapter` reports the link to the discovered mitch with `id` as gone.
+    Disconnected { id: String, adapter: String },
+    /// The adapters of the system and what the discovery does with them, sent on every change.
+    Adapters(Vec<AdapterInfo>),
+    /// A failure of the mitch named `device`, or of the discovery if `None`.
+    Error {
+        device: Option<String>,
+        error: String,
+    },
+    /// A failure of the mitch named `device` that needs no attention, it is only logged.
+    Notice { device: String, message: String },
 }
 
+/// Interval at which the adapters are listed again, to pick up ones plugged in.
+const ADAPTER_RETRY: Duration = Duration::from_secs(2);
+
+/// Scans with every chosen adapter at once.
 pub struct BtleDiscoverTask {
     sender: mpsc::UnboundedSender<Event>,
-}
-
-async fn get_central(manager: &Manager) -> Adapter {
-    let adapters = manager.adapters().await.unwrap();
-    adapters.into_iter().next().unwrap()
+    adapter: AdapterChoice,
+    /// Known devices, applied to a mitch before it is announced.
+    registry: Registry,
+    /// The adapters of the system as last listed.
+    adapters: Mutex<Vec<AdapterInfo>>,
+    /// Ids of the mitches announced by any adapter. Locked while announcing, so a mitch is
+    /// always discovered before it is reachable through further adapters.
+    announced: tokio::sync::Mutex<HashSet<String>>,
 }
 
 impl BtleDiscoverTask {
-    /// Constructs a new instance of [`EventThread`].
-    pub fn new(sender: mpsc::UnboundedSender<Event>) -> Self {
-        Self { sender }
+    /// Constructs a new instance of [`EventThread`] scanning with the chosen `adapter`, setting
+    /// up known devices as `registry` says.
+    pub fn new(
+        sender: mpsc::UnboundedSender<Event>,
+        adapter: AdapterChoice,
+        registry: Registry,
+    ) -> Self {
+        Self {
+            sender,
+            adapter,
+            registry,
+            adapters: Mutex::new(Vec::new()),
+            announced: tokio::sync::Mutex::new(HashSet::new()),
+        }
     }
 
     /// Runs the blte discovery thread.
     ///
-    /// This function emits mitch discovered events
-    pub async fn run(self) -> color_eyre::Result<()> {
-        let manager = Manager::new().await?;
-
-        let central = get_central(&manager).await;
+    /// This function emits mitch discovered events, and an error event if the discovery fails.
+    pub async fn run(self) {
+        let task = Arc::new(self);
+        if let Err(e) = task.clone().discover().await {
+            task.send(Event::Bluetooth(BluetoothEvent::Error {
+                device: None,
+                error: format!("Discovery failed: {e}"),
+            }));
+        }
+    }
 
-        let central_state = central.adapter_state().await.unwrap();
+    /// Lists the adapters again and again and scans with every chosen one that is not yet.
+    async fn discover(self: Arc<Self>) -> color_eyre::Result<()> {
+        let manager = Manager::new().await?;
+        // Dropping the set when the discovery is aborted ends the scans as well
+        let mut scans = JoinSet::new();
+        let mut running = HashSet::new();
+        loop {
+            let mut found = Vec::new();
+            for adapter in manager.adapters().await? {
+                found.push((adapter.adapter_info().await?, adapter));
+            }
+            self.list(&found, &running);
+            for (i, (name, central)) in found.into_iter().enumerate() {
+                if self.adapter.picks(i, &name) && running.insert(name.clone()) {
+                    let task = self.clone();
+                    scans.spawn(async move {
+                        task.scan_adapter(&name, central).await;
+                        name
+                    });
+                }
+            }
+            select! {
+                _ = tokio::time::sleep(ADAPTER_RETRY) => {}
+                Some(Ok(name)) = scans.join_next() => {
+                    running.remove(&name);
+                }
+            }
+        }
+    }
 
-        if central_state != CentralState::PoweredOn {
-            self.send(Event::Bluetooth(BluetoothEvent::NotActive));
-            return Ok(());
+    /// Takes over the `found` adapters, keeping the state of the ones `running` a scan.
+    fn list(&self, found: &[(String, Adapter)], running: &HashSet<String>) {
+        let mut adapters = self.adapters.lock().unwrap();
+        let listed = found
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| {
+                let state = match adapters.iter().find(|a| &a.name == name) {
+                    Some(known) if running.contains(name) => known.state,
+                    _ if self.adapter.picks(i, name) => AdapterState::Starting,
+                    _ => AdapterState::Unused,
+                };
+                AdapterInfo {
+                    name: name.clone(),
+                    state,
+                }
+            })
+            .collect();
+        if *adapters != listed {
+            *adapters = listed;
+            self.send(Event::Bluetooth(BluetoothEvent::Adapters(adapters.clone())));
         }
+    }
 
-        // Each adapter has an event stream, we fetch via events(),
-        // simplifying the type, this will return what is essentially a
-        // Future<Result<Stream<Item=CentralEvent>>>.
-        let mut events = central.events().await?;
+    fn set_state(&self, name: &str, state: AdapterState) {
+        let mut adapters = self.adapters.lock().unwrap();
+        if let Some(adapter) = adapters.iter_mut().find(|a| a.name == name) {
+            adapter.state = state;
+        }
+        self.send(Event::Bluetooth(BluetoothEvent::Adapters(adapters.clone())));
+    }
 
-        central.start_scan(ScanFilter::default()).await?;
+    /// Scans with the adapter called `name`, waiting for it to be powered on, which is waited
+    /// for again whenever it is powered off. Ends when the adapter is removed.
+    async fn scan_adapter(&self, name: &str, central: Adapter) {
+        let result: color_eyre::Result<()> = async {
+            // Each adapter has an event stream, we fetch via events(),
+            // simplifying the type, this will return what is essentially a
+            // Future<Result<Stream<Item=CentralEvent>>>.
+            let mut events = central.events().await?;
+            // Peripherals of this adapter announced as mitch, by the id of the mitch
+            let mut known = HashMap::new();
+            loop {
+                if central.adapter_state().await? != CentralState::PoweredOn {
+                    self.set_state(name, AdapterState::PoweredOff);
+                    if !powered_on(&mut events).await {
+                        return Ok(());
+                    }
+                }
+                central.start_scan(ScanFilter::default()).await?;
+                self.set_state(name, AdapterState::Scanning);
+                if !self.scan(name, &central, &mut events, &mut known).await {
+                    return Ok(());
+                }
+            }
+        }
+        .await;
+        if let Err(e) = result {
+            self.send(Event::Bluetooth(BluetoothEvent::Error {
+                device: None,
+                error: format!("Scanning with {name} failed: {e}"),
+            }));
+        }
+    }
 
+    /// Handles the events of a scanning adapter, `true` once it is powered off and `false` if
+    /// it was removed.
+    async fn scan(
+        &self,
+        adapter: &str,
+        central: &Adapter,
+        events: &mut (impl Stream<Item = CentralEvent> + Unpin),
+        known: &mut HashMap<PeripheralId, String>,
+    ) -> bool {
         while let Some(event) = events.next().await {
-            if let CentralEvent::DeviceDiscovered(id) = event {
-                let peripheral = central.peripheral(&id).await?;
-                let properties = peripheral.properties().await?;
-                let name = properties
-                    .and_then(|p| p.local_name)
-                    .unwrap_or_default()
-                    .to_lowercase();
-                if name.starts_with("mitch") {
-                    self.send(Event::Bluetooth(BluetoothEvent::Discovered(
-                        Mitch::new(name.clone(), peripheral.clone()).await?,
-                    )));
+            match event {
+                CentralEvent::StateUpdate(state) if state != CentralState::PoweredOn => {
+                    return true;
+                }
+                CentralEvent::DeviceDiscovered(id) | CentralEvent::DeviceUpdated(id) => {
+                    // A peripheral vanishing while being looked at must not end the scan
+                    if let Err(e) = self.inspect(adapter, central, &id, known).await {
+                        self.send(Event::Bluetooth(BluetoothEvent::Error {
+                            device: None,
+                            error: format!("Inspecting {id} failed: {e}"),
+                        }));
+                    }
+                }
+                CentralEvent::DeviceConnected(id) => {
+                    if let Some(id) = known.get(&id) {
+                        self.send(Event::Bluetooth(BluetoothEvent::Connected {
+                            id: id.clone(),
+                            adapter: adapter.to_string(),
+                        }));
+                    }
+                }
+                CentralEvent::DeviceDisconnected(id) => {
+                    if let Some(id) = known.get(&id) {
+                        self.send(Event::Bluetooth(BluetoothEvent::Disconnected {
+                            id: id.clone(),
+                            adapter: adapter.to_string(),
+                        }));
+                    }
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Announces the peripheral with `id` the first time it shows up as a mitch, set up as the
+    /// registry says if it is known, and sends an updated event with its name, signal strength
+    /// and transmit power from then on.
+    ///
+    /// A mitch announced by another adapter before is only reported as reachable.
+    async fn inspect(
+        &self,
+        adapter: &str,
+        central: &Adapter,
+        id: &PeripheralId,
+        known: &mut HashMap<PeripheralId, String>,
+    ) -> color_eyre::Result<()> {
+        let peripheral = central.peripheral(id).await?;
+        let (name, rssi, tx_power) = peripheral
+            .properties()
+            .await?
+            .map(|p| {
+                (
+                    p.local_name.map(|n| n.to_lowercase()),
+                    p.rssi,
+                    p.tx_power_level,
+                )
+            })
+            .unwrap_or_default();
+        // The name may only come with a later advertisement
+        if !known.contains_key(id)
+            && let Some(name) = name.as_ref().filter(|n| n.starts_with("mitch"))
+        {
+            let transport: Arc<dyn MitchTransport> =
+                Arc::new(BtleTransport::new(peripheral, adapter.to_string()));
+            let mitch_id = transport.id();
+            let mut announced = self.announced.lock().await;
+            if announced.insert(mitch_id.clone()) {
+                let mut mitch = Mitch::new(name.clone(), transport).await?;
+                if let Some(entry) = self.registry.get(&mitch_id) {
+                    mitch.apply(entry);
                 }
+                self.send(Event::Bluetooth(BluetoothEvent::Discovered(Box::new(
+                    mitch,
+                ))));
+            } else {
+                self.send(Event::Bluetooth(BluetoothEvent::Reachable(transport)));
             }
+            known.insert(id.clone(), mitch_id);
+        }
+        if let Some(id) = known.get(id) {
+            self.send(Event::Bluetooth(BluetoothEvent::Updated {
+                id: id.clone(),
+                name,
+                rssi,
+                tx_power,
+            }));
         }
         Ok(())
     }
@@ -78,3 +314,13 @@ impl BtleDiscoverTask {
         let _ = self.sender.send(event);
     }
 }
+
+/// Waits until the adapter reports being powered on, `false` if its events ended before.
+async fn powered_on(events: &mut (impl Stream<Item = CentralEvent> + Unpin)) -> bool {
+    while let Some(event) = events.next().await {
+        if let CentralEvent::StateUpdate(CentralState::PoweredOn) = event {
+            return true;
+        }
+    }
+    false
+}
@@ -0,0 +1,114 @@
+//! LSL stream descriptions for mitch data streams.
+//!
+//! The `<desc>` element follows the XDF meta-data conventions: one `<channel>` per value of a
+//! [`Sample`](super::sample::Sample) with label, type and unit, and the device under
+//! `<acquisition>`.
+
+use std::ops::Deref;
+
+use lsl::{ChannelFormat, StreamInfo, StreamOutlet};
+
+use super::{info::DeviceInfo, protocol::StreamConfig, subject::Side};
+
+/// Stream type announced to LSL consumers.
+pub const STREAM_TYPE: &str = "Motion";
+
+/// An LSL outlet that can be moved into and shared between tasks.
+pub struct SendOutlet(StreamOutlet);
+
+// SAFETY: the outlet only holds the handle of a liblsl outlet, which liblsl documents as safe to
+// use from any thread, including concurrent pushes. The bindings merely do not mark the raw
+// handle as `Send`/`Sync`.
+unsafe impl Send for SendOutlet {}
+unsafe impl Sync for SendOutlet {}
+
+impl SendOutlet {
+    /// Opens an outlet for `info` that buffers up to 360 seconds of samples.
+    pub fn new(info: &StreamInfo) -> color_eyre::Result<Self> {
+        Ok(Self(StreamOutlet::new(info, 1, 360)?))
+    }
+}
+
+impl Deref for SendOutlet {
+    type Target = StreamOutlet;
+
+    fn deref(&self) -> &StreamOutlet {
+        &self.0
+    }
+}
+
+/// Builds the [`StreamInfo`] for a mitch streaming with `config`.
+///
+/// The device address doubles as source id so consumers can recover the stream after a restart.
+/// Device details not known yet are left out of the description.
+pub fn stream_info(
+    name: &str,
+    address: &str,
+    device: &DeviceInfo,
+    config: StreamConfig,
+) -> color_eyre::Result<StreamInfo> {
+    let channels = config.mode.channels();
+    let mut info = StreamInfo::new(
+        name,
+        STREAM_TYPE,
+        channels.len() as u32,
+        config.frequency.hz(),
+        ChannelFormat::Double64,
+        address,
+    )?;
+    let mut desc = info.desc();
+    let mut xml_channels = desc.append_child("channels");
+    for channel in channels {
+        let mut c = xml_channels.append_child("channel");
+        c.append_child_value("label", &channel.label);
+        c.append_child_value("type", channel.kind);
+        c.append_child_value("unit", channel.unit);
+    }
+    let mut acquisition = desc.append_child("acquisition");
+    acquisition.append_child_value("model", "mitch");
+    acquisition.append_child_value("name", name);
+    acquisition.append_child_value("address", address);
+    if let Some(serial) = &device.serial {
+        acquisition.append_child_value("serial_number", serial);
+    }
+    if let Some(hardware) = device.hardware {
+        acquisition.append_child_value("hardware", &hardware.to_string());
+    }
+    if let Some(firmware) = device.firmware {
+        acquisition.append_child_value("firmware", &firmware.to_string());
+    }
+    acquisition.append_child_value("mode", &format!("{:?}", config.mode));
+    Ok(info)
+}
+
+/// Builds the [`StreamInfo`] for the combined stream of the subject `name` whose insoles both
+/// stream with `config`.
+///
+/// The channels of the left insole come first, each label prefixed by its side like `L_P1`.
+/// The subject name doubles as source id.
+pub fn pair_stream_info(name: &str, config: StreamConfig) -> color_eyre::Result<StreamInfo> {
+    let channels = config.mode.channels();
+    let mut info = StreamInfo::new(
+        name,
+        STREAM_TYPE,
+        2 * channels.len() as u32,
+        config.frequency.hz(),
+        ChannelFormat::Double64,
+        name,
+    )?;
+    let mut desc = info.desc();
+    let mut xml_channels = desc.append_child("channels");
+    for side in [Side::Left, Side::Right] {
+        for channel in &channels {
+            let mut c = xml_channels.append_child("channel");
+            c.append_child_value("label", &format!("{}_{}", side.letter(), channel.label));
+            c.append_child_value("type", channel.kind);
+            c.append_child_value("unit", channel.unit);
+        }
+    }
+    let mut acquisition = desc.append_child("acquisition");
+    acquisition.append_child_value("model", "mitch pair");
+    acquisition.append_child_value("subject", name);
+    acquisition.append_child_value("mode", &format!("{:?}", config.mode));
+    Ok(info)
+}
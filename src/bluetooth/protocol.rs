@@ -0,0 +1,729 @@
+//! Command/response codec for the mitch command characteristic.
+//!
+//! Commands are written to [`COMMAND_CHAR`](super::mitch::COMMAND_CHAR) as
+//! `[id, len, payload..]` and the device answers on the same characteristic with
+//! `[ack, len, id, error, payload..]`. In both directions `len` counts the bytes following the
+//! length byte.
+//!
+//! Only the ids of [`Command::SET_STATE`] and [`Command::GET_STATE`] are confirmed, they are the
+//! ones the mitch firmware is known to answer, including the start and stop stream payloads of
+//! `SET_STATE`. All other ids are unconfirmed: no firmware reference is at hand for them, they
+//! follow the pattern of the confirmed pair with setters below `0x80` and queries above, and
+//! the [simulator](super::sim) answers them the same way. Check them against the firmware before
+//! relying on battery, info, log, clock or bootloader commands on real devices.
+
+use std::fmt;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+
+use super::{mitch::MitchState, sample::StreamMode};
+
+/// First byte of every reply the device sends to a command.
+pub const ACK: u8 = 0x00;
+
+/// Size of the reply header: ack, length, command echo and error code.
+const RESPONSE_HEADER_LEN: usize = 4;
+
+/// A command understood by the mitch firmware.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Query the current [`MitchState`].
+    GetState,
+    /// Switch the device into the given state without any arguments.
+    SetState(MitchState),
+    /// Switch the device into [`MitchState::SysTx`] with the given stream configuration. The
+    /// device replies with the time of its first sample, see [`Response::time`].
+    StartStream(StreamConfig),
+    /// Switch the device into [`MitchState::SysLog`], recording to its memory with the given
+    /// configuration until told otherwise, whether connected or not.
+    StartLog(StreamConfig),
+    /// Query the number of log files in the memory of the device.
+    GetFileCount,
+    /// Query configuration, size, checksum and start time of a log file.
+    GetFileInfo(u8),
+    /// Switch the device into [`MitchState::SysReadout`] and send the log file from `offset`
+    /// on as data notifications of the form `[offset: u32 LE, data..]`.
+    ReadFile { file: u8, offset: u32 },
+    /// Delete all log files.
+    EraseMemory,
+    /// Query hardware revision and firmware version.
+    GetFirmwareVersion,
+    /// Query the battery charge in percent.
+    GetBatteryCharge,
+    /// Query the unique id of the device.
+    GetDeviceId,
+    /// Query free and total log memory in bytes.
+    GetMemoryStatus,
+    /// Set the real-time clock of the device, in microseconds since the Unix epoch.
+    SetTime(u64),
+    /// Query the real-time clock of the device.
+    GetTime,
+    /// Reboot into the bootloader, ending in [`MitchState::BootIdle`].
+    EnterBootloader,
+    /// Announce a firmware image, switching the bootloader into [`MitchState::BootDownload`].
+    FirmwareBegin {
+        size: u32,
+        crc: u32,
+        version: FirmwareVersion,
+    },
+    /// Part of the announced firmware image starting at `offset`.
+    FirmwareChunk { offset: u32, data: Vec<u8> },
+    /// Let the bootloader check the received image against the announced checksum.
+    FirmwareVerify,
+    /// Reboot into the application.
+    Reboot,
+}
+
+impl Command {
+    /// Confirmed, sent by the app since its first version.
+    pub const SET_STATE: u8 = 0x02;
+    /// Confirmed, sent by the app since its first version.
+    pub const GET_STATE: u8 = 0x82;
+    // Unconfirmed from here on, see the module documentation
+    pub const SET_TIME: u8 = 0x06;
+    pub const GET_TIME: u8 = 0x86;
+    pub const GET_BATTERY_CHARGE: u8 = 0x87;
+    pub const GET_DEVICE_ID: u8 = 0x88;
+    pub const GET_MEMORY_STATUS: u8 = 0x89;
+    pub const READ_FILE: u8 = 0x0A;
+    pub const GET_FILE_COUNT: u8 = 0x8B;
+    pub const GET_FILE_INFO: u8 = 0x8C;
+    pub const ERASE_MEMORY: u8 = 0x0D;
+    pub const GET_FIRMWARE_VERSION: u8 = 0x8E;
+    pub const ENTER_BOOTLOADER: u8 = 0x10;
+    pub const FIRMWARE_BEGIN: u8 = 0x11;
+    pub const FIRMWARE_CHUNK: u8 = 0x12;
+    pub const FIRMWARE_VERIFY: u8 = 0x13;
+    pub const REBOOT: u8 = 0x14;
+
+    /// Largest firmware chunk that fits into a command next to its offset.
+    pub const MAX_CHUNK: usize = 128;
+    /// Longest payload the length byte of a command can announce.
+    pub const MAX_PAYLOAD: usize = u8::MAX as usize;
+
+    /// Identifier of the command, echoed back by the device in its reply.
+    pub fn id(&self) -> u8 {
+        match self {
+            Command::GetState => Self::GET_STATE,
+            Command::SetState(_) | Command::StartStream(_) | Command::StartLog(_) => {
+                Self::SET_STATE
+            }
+            Command::GetFileCount => Self::GET_FILE_COUNT,
+            Command::GetFileInfo(_) => Self::GET_FILE_INFO,
+            Command::ReadFile { .. } => Self::READ_FILE,
+            Command::EraseMemory => Self::ERASE_MEMORY,
+            Command::GetFirmwareVersion => Self::GET_FIRMWARE_VERSION,
+            Command::GetBatteryCharge => Self::GET_BATTERY_CHARGE,
+            Command::GetDeviceId => Self::GET_DEVICE_ID,
+            Command::GetMemoryStatus => Self::GET_MEMORY_STATUS,
+            Command::SetTime(_) => Self::SET_TIME,
+            Command::GetTime => Self::GET_TIME,
+            Command::EnterBootloader => Self::ENTER_BOOTLOADER,
+            Command::FirmwareBegin { .. } => Self::FIRMWARE_BEGIN,
+            Command::FirmwareChunk { .. } => Self::FIRMWARE_CHUNK,
+            Command::FirmwareVerify => Self::FIRMWARE_VERIFY,
+            Command::Reboot => Self::REBOOT,
+        }
+    }
+
+    /// Arguments of the command.
+    pub fn payload(&self) -> Vec<u8> {
+        match *self {
+            Command::GetState
+            | Command::GetFileCount
+            | Command::EraseMemory
+            | Command::GetFirmwareVersion
+            | Command::GetBatteryCharge
+            | Command::GetDeviceId
+            | Command::GetMemoryStatus
+            | Command::GetTime
+            | Command::EnterBootloader
+            | Command::FirmwareVerify
+            | Command::Reboot => Vec::new(),
+            Command::SetState(state) => vec![state as u8],
+            Command::StartStream(config) => config.payload(MitchState::SysTx),
+            Command::StartLog(config) => config.payload(MitchState::SysLog),
+            Command::SetTime(time) => time.to_le_bytes().to_vec(),
+            Command::GetFileInfo(file) => vec![file],
+            Command::ReadFile { file, offset } => {
+                let mut payload = vec![file];
+                payload.extend_from_slice(&offset.to_le_bytes());
+                payload
+            }
+            Command::FirmwareBegin { size, crc, version } => {
+                let mut payload = size.to_le_bytes().to_vec();
+                payload.extend_from_slice(&crc.to_le_bytes());
+                payload.extend_from_slice(&[version.major, version.minor, version.patch]);
+                payload
+            }
+            Command::FirmwareChunk { offset, ref data } => {
+                let mut payload = offset.to_le_bytes().to_vec();
+                payload.extend_from_slice(data);
+                payload
+            }
+        }
+    }
+
+    /// Decodes the bytes written to the command characteristic, the inverse of
+    /// [`Command::encode`].
+    pub fn decode(bytes: &[u8]) -> Result<Self, ProtocolError> {
+        if bytes.len() < 2 {
+            return Err(ProtocolError::Truncated {
+                expected: 2,
+                actual: bytes.len(),
+            });
+        }
+        let len = bytes[1] as usize;
+        let Some(payload) = bytes.get(2..len + 2) else {
+            return Err(ProtocolError::Truncated {
+                expected: len + 2,
+                actual: bytes.len(),
+            });
+        };
+        match (bytes[0], payload) {
+            (Self::GET_STATE, []) => Ok(Command::GetState),
+            (Self::SET_STATE, &[state, mode, frequency]) => {
+                let config = StreamConfig::parse(mode, frequency)?;
+                match MitchState::try_from(state) {
+                    Ok(MitchState::SysTx) => Ok(Command::StartStream(config)),
+                    Ok(MitchState::SysLog) => Ok(Command::StartLog(config)),
+                    _ => Err(ProtocolError::InvalidArgument(state)),
+                }
+            }
+            (Self::SET_STATE, &[state]) => Ok(Command::SetState(
+                MitchState::try_from(state).map_err(|_| ProtocolError::UnknownState(state))?,
+            )),
+            (Self::GET_FILE_COUNT, []) => Ok(Command::GetFileCount),
+            (Self::GET_FILE_INFO, &[file]) => Ok(Command::GetFileInfo(file)),
+            (Self::READ_FILE, &[file, a, b, c, d]) => Ok(Command::ReadFile {
+                file,
+                offset: u32::from_le_bytes([a, b, c, d]),
+            }),
+            (Self::ERASE_MEMORY, []) => Ok(Command::EraseMemory),
+            (Self::GET_FIRMWARE_VERSION, []) => Ok(Command::GetFirmwareVersion),
+            (Self::GET_BATTERY_CHARGE, []) => Ok(Command::GetBatteryCharge),
+            (Self::GET_DEVICE_ID, []) => Ok(Command::GetDeviceId),
+            (Self::GET_MEMORY_STATUS, []) => Ok(Command::GetMemoryStatus),
+            (Self::SET_TIME, &[t0, t1, t2, t3, t4, t5, t6, t7]) => {
+                Ok(Command::SetTime(u64::from_le_bytes([
+                    t0, t1, t2, t3, t4, t5, t6, t7,
+                ])))
+            }
+            (Self::GET_TIME, []) => Ok(Command::GetTime),
+            (Self::ENTER_BOOTLOADER, []) => Ok(Command::EnterBootloader),
+            (Self::FIRMWARE_BEGIN, &[s0, s1, s2, s3, c0, c1, c2, c3, major, minor, patch]) => {
+                Ok(Command::FirmwareBegin {
+                    size: u32::from_le_bytes([s0, s1, s2, s3]),
+                    crc: u32::from_le_bytes([c0, c1, c2, c3]),
+                    version: FirmwareVersion {
+                        major,
+                        minor,
+                        patch,
+                    },
+                })
+            }
+            (Self::FIRMWARE_CHUNK, &[o0, o1, o2, o3, ref data @ ..]) => {
+                Ok(Command::FirmwareChunk {
+                    offset: u32::from_le_bytes([o0, o1, o2, o3]),
+                    data: data.to_vec(),
+                })
+            }
+            (Self::FIRMWARE_VERIFY, []) => Ok(Command::FirmwareVerify),
+            (Self::REBOOT, []) => Ok(Command::Reboot),
+            (id, _) => Err(ProtocolError::UnknownCommand(id)),
+        }
+    }
+
+    /// Encodes the command into the bytes written to the command characteristic.
+    ///
+    /// Fails if the payload is longer than [`Command::MAX_PAYLOAD`].
+    pub fn encode(&self) -> Result<Vec<u8>, ProtocolError> {
+        let payload = self.payload();
+        let len = u8::try_from(payload.len()).map_err(|_| ProtocolError::PayloadTooLong {
+            len: payload.len(),
+            max: Self::MAX_PAYLOAD,
+        })?;
+        let mut bytes = Vec::with_capacity(payload.len() + 2);
+        bytes.push(self.id());
+        bytes.push(len);
+        bytes.extend_from_slice(&payload);
+        Ok(bytes)
+    }
+}
+
+/// Sampling frequencies supported by the firmware.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum Frequency {
+    Hz5 = 0x01,
+    Hz10 = 0x02,
+    Hz25 = 0x03,
+    #[default]
+    Hz50 = 0x04,
+    Hz100 = 0x05,
+    Hz200 = 0x06,
+}
+
+impl TryFrom<u8> for Frequency {
+    type Error = color_eyre::eyre::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|f| *f as u8 == value)
+            .ok_or_else(|| eyre!("Unknown frequency: {value}"))
+    }
+}
+
+impl Frequency {
+    pub const ALL: [Frequency; 6] = [
+        Frequency::Hz5,
+        Frequency::Hz10,
+        Frequency::Hz25,
+        Frequency::Hz50,
+        Frequency::Hz100,
+        Frequency::Hz200,
+    ];
+
+    /// Sampling rate in Hz.
+    pub fn hz(&self) -> f64 {
+        match self {
+            Frequency::Hz5 => 5.0,
+            Frequency::Hz10 => 10.0,
+            Frequency::Hz25 => 25.0,
+            Frequency::Hz50 => 50.0,
+            Frequency::Hz100 => 100.0,
+            Frequency::Hz200 => 200.0,
+        }
+    }
+
+    /// The next higher frequency, wrapping around to the lowest one.
+    pub fn next(&self) -> Self {
+        let i = Self::ALL.iter().position(|f| f == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+}
+
+/// What the device streams or logs and how fast.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamConfig {
+    pub mode: StreamMode,
+    pub frequency: Frequency,
+}
+
+impl StreamConfig {
+    /// Parses the mode and frequency bytes used in commands and replies.
+    pub fn parse(mode: u8, frequency: u8) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            mode: StreamMode::try_from(mode).map_err(|_| ProtocolError::InvalidArgument(mode))?,
+            frequency: Frequency::try_from(frequency)
+                .map_err(|_| ProtocolError::InvalidArgument(frequency))?,
+        })
+    }
+
+    /// Arguments of a state change into `state` using this configuration.
+    fn payload(&self, state: MitchState) -> Vec<u8> {
+        vec![state as u8, self.mode as u8, self.frequency as u8]
+    }
+}
+
+/// Version of the firmware running on a device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// A validated reply to a [`Command`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub command: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Response {
+    /// Longest payload the length byte of a reply can announce next to command echo and error
+    /// code.
+    pub const MAX_PAYLOAD: usize = u8::MAX as usize - 2;
+
+    /// Parses the bytes read from the command characteristic as the reply to `command`.
+    ///
+    /// Trailing bytes beyond the announced length are ignored since some stacks pad the
+    /// characteristic value.
+    pub fn parse(command: &Command, bytes: &[u8]) -> Result<Self, ProtocolError> {
+        if bytes.len() < RESPONSE_HEADER_LEN {
+            return Err(ProtocolError::Truncated {
+                expected: RESPONSE_HEADER_LEN,
+                actual: bytes.len(),
+            });
+        }
+        if bytes[0] != ACK {
+            return Err(ProtocolError::NotAcknowledged(bytes[0]));
+        }
+        let len = bytes[1] as usize;
+        if len < 2 {
+            return Err(ProtocolError::InvalidLength(bytes[1]));
+        }
+        if bytes.len() < len + 2 {
+            return Err(ProtocolError::Truncated {
+                expected: len + 2,
+                actual: bytes.len(),
+            });
+        }
+        if bytes[2] != command.id() {
+            return Err(ProtocolError::UnexpectedCommand {
+                expected: command.id(),
+                actual: bytes[2],
+            });
+        }
+        if bytes[3] != 0 {
+            return Err(ProtocolError::Device(bytes[3]));
+        }
+        Ok(Self {
+            command: bytes[2],
+            payload: bytes[RESPONSE_HEADER_LEN..len + 2].to_vec(),
+        })
+    }
+
+    /// Encodes a successful reply, the inverse of [`Response::parse`].
+    ///
+    /// Fails if the payload is longer than [`Response::MAX_PAYLOAD`].
+    pub fn encode(&self) -> Result<Vec<u8>, ProtocolError> {
+        if self.payload.len() > Self::MAX_PAYLOAD {
+            return Err(ProtocolError::PayloadTooLong {
+                len: self.payload.len(),
+                max: Self::MAX_PAYLOAD,
+            });
+        }
+        let mut bytes = vec![ACK, self.payload.len() as u8 + 2, self.command, 0];
+        bytes.extend_from_slice(&self.payload);
+        Ok(bytes)
+    }
+
+    /// Encodes a reply rejecting `command` with the error `code`.
+    pub fn encode_error(command: u8, code: u8) -> Vec<u8> {
+        vec![ACK, 2, command, code]
+    }
+
+    /// Returns the first `N` bytes of the payload.
+    pub fn take<const N: usize>(&self) -> Result<[u8; N], ProtocolError> {
+        self.payload
+            .get(..N)
+            .and_then(|b| b.try_into().ok())
+            .ok_or(ProtocolError::Truncated {
+                expected: RESPONSE_HEADER_LEN + N,
+                actual: RESPONSE_HEADER_LEN + self.payload.len(),
+            })
+    }
+
+    /// Interprets the payload as the reply to [`Command::GetState`].
+    pub fn state(&self) -> Result<MitchState, ProtocolError> {
+        let [byte] = self.take()?;
+        MitchState::try_from(byte).map_err(|_| ProtocolError::UnknownState(byte))
+    }
+
+    /// Interprets the payload as the reply to [`Command::GetFirmwareVersion`], returning the
+    /// hardware revision and the firmware version.
+    pub fn firmware_version(&self) -> Result<(u8, FirmwareVersion), ProtocolError> {
+        let [hardware, major, minor, patch] = self.take()?;
+        Ok((
+            hardware,
+            FirmwareVersion {
+                major,
+                minor,
+                patch,
+            },
+        ))
+    }
+
+    /// Interprets the payload as the reply to [`Command::GetBatteryCharge`].
+    pub fn battery_charge(&self) -> Result<u8, ProtocolError> {
+        let [percent] = self.take()?;
+        if percent > 100 {
+            return Err(ProtocolError::InvalidArgument(percent));
+        }
+        Ok(percent)
+    }
+
+    /// Interprets the payload as the reply to [`Command::GetDeviceId`].
+    pub fn device_id(&self) -> Result<u64, ProtocolError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    /// Interprets the payload as the reply to [`Command::GetMemoryStatus`], returning free and
+    /// total bytes.
+    pub fn memory_status(&self) -> Result<(u32, u32), ProtocolError> {
+        let [f0, f1, f2, f3, t0, t1, t2, t3] = self.take()?;
+        let free = u32::from_le_bytes([f0, f1, f2, f3]);
+        let total = u32::from_le_bytes([t0, t1, t2, t3]);
+        if free > total {
+            return Err(ProtocolError::InvalidMemory { free, total });
+        }
+        Ok((free, total))
+    }
+
+    /// Interprets the payload as the reply to [`Command::GetTime`] or [`Command::StartStream`],
+    /// a device time in microseconds since the Unix epoch.
+    pub fn time(&self) -> Result<u64, ProtocolError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    /// Interprets the payload as the reply to [`Command::GetFileCount`].
+    pub fn file_count(&self) -> Result<u8, ProtocolError> {
+        let [count] = self.take()?;
+        Ok(count)
+    }
+}
+
+/// Everything that can be wrong with a reply from the device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// The reply is shorter than its header or announced length.
+    Truncated { expected: usize, actual: usize },
+    /// The first byte of the reply is not [`ACK`].
+    NotAcknowledged(u8),
+    /// The announced length cannot hold the command echo and error code.
+    InvalidLength(u8),
+    /// The reply belongs to a different command than the one sent.
+    UnexpectedCommand { expected: u8, actual: u8 },
+    /// The device rejected the command with the given error code.
+    Device(u8),
+    /// The device reported a state unknown to [`MitchState`].
+    UnknownState(u8),
+    /// The command id is not known.
+    UnknownCommand(u8),
+    /// A command argument is out of range.
+    InvalidArgument(u8),
+    /// The device reports more free memory than it has.
+    InvalidMemory { free: u32, total: u32 },
+    /// A payload does not fit the length byte of a command or reply.
+    PayloadTooLong { len: usize, max: usize },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Truncated { expected, actual } => {
+                write!(
+                    f,
+                    "reply truncated: expected {expected} bytes, got {actual}"
+                )
+            }
+            ProtocolError::NotAcknowledged(byte) => {
+                write!(f, "reply not acknowledged: header {byte:#04x}")
+            }
+            ProtocolError::InvalidLength(len) => write!(f, "invalid reply length {len}"),
+            ProtocolError::UnexpectedCommand { expected, actual } => write!(
+                f,
+                "reply to command {actual:#04x} while waiting for {expected:#04x}"
+            ),
+            ProtocolError::Device(code) => write!(f, "device returned error code {code:#04x}"),
+            ProtocolError::UnknownState(state) => write!(f, "unknown state {state:#04x}"),
+            ProtocolError::UnknownCommand(id) => write!(f, "unknown command {id:#04x}"),
+            ProtocolError::InvalidArgument(arg) => write!(f, "invalid argument {arg:#04x}"),
+            ProtocolError::InvalidMemory { free, total } => {
+                write!(f, "device reports {free} of {total} bytes free")
+            }
+            ProtocolError::PayloadTooLong { len, max } => {
+                write!(f, "payload of {len} bytes exceeds the maximum of {max}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: StreamConfig = StreamConfig {
+        mode: StreamMode::Imu6,
+        frequency: Frequency::Hz100,
+    };
+
+    #[test]
+    fn every_command_round_trips() {
+        let commands = [
+            Command::GetState,
+            Command::SetState(MitchState::SysIdle),
+            Command::SetState(MitchState::SysStandby),
+            Command::StartStream(CONFIG),
+            Command::StartLog(StreamConfig::default()),
+            Command::GetFileCount,
+            Command::GetFileInfo(3),
+            Command::ReadFile {
+                file: 1,
+                offset: 0x0102_0304,
+            },
+            Command::EraseMemory,
+            Command::GetFirmwareVersion,
+            Command::GetBatteryCharge,
+            Command::GetDeviceId,
+            Command::GetMemoryStatus,
+            Command::SetTime(1_700_000_000_123_456),
+            Command::GetTime,
+            Command::EnterBootloader,
+            Command::FirmwareBegin {
+                size: 40_000,
+                crc: 0xdead_beef,
+                version: FirmwareVersion {
+                    major: 1,
+                    minor: 2,
+                    patch: 3,
+                },
+            },
+            Command::FirmwareChunk {
+                offset: 256,
+                data: vec![0xab; Command::MAX_CHUNK],
+            },
+            Command::FirmwareVerify,
+            Command::Reboot,
+        ];
+        for command in commands {
+            let bytes = command.encode().unwrap();
+            assert_eq!(bytes[0], command.id(), "{command:?}");
+            assert_eq!(bytes[1] as usize, bytes.len() - 2, "{command:?}");
+            assert_eq!(Command::decode(&bytes), Ok(command));
+        }
+    }
+
+    #[test]
+    fn limits_payload_length() {
+        let chunk = |len| Command::FirmwareChunk {
+            offset: 0,
+            data: vec![0; len - 4],
+        };
+        let longest = chunk(Command::MAX_PAYLOAD).encode().unwrap();
+        assert_eq!(longest[1], 255);
+        assert_eq!(Command::decode(&longest), Ok(chunk(Command::MAX_PAYLOAD)));
+        assert_eq!(
+            chunk(Command::MAX_PAYLOAD + 1).encode(),
+            Err(ProtocolError::PayloadTooLong { len: 256, max: 255 })
+        );
+
+        let reply = |len| Response {
+            command: Command::GET_FILE_INFO,
+            payload: vec![0; len],
+        };
+        let longest = reply(Response::MAX_PAYLOAD).encode().unwrap();
+        assert_eq!(longest[1], 255);
+        assert_eq!(
+            Response::parse(&Command::GetFileInfo(0), &longest),
+            Ok(reply(Response::MAX_PAYLOAD))
+        );
+        assert_eq!(
+            reply(Response::MAX_PAYLOAD + 1).encode(),
+            Err(ProtocolError::PayloadTooLong { len: 254, max: 253 })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        assert_eq!(
+            Command::decode(&[Command::GET_STATE]),
+            Err(ProtocolError::Truncated {
+                expected: 2,
+                actual: 1
+            })
+        );
+        assert_eq!(
+            Command::decode(&[Command::READ_FILE, 5, 1, 0]),
+            Err(ProtocolError::Truncated {
+                expected: 7,
+                actual: 4
+            })
+        );
+        assert_eq!(
+            Command::decode(&[0x42, 0]),
+            Err(ProtocolError::UnknownCommand(0x42))
+        );
+        assert_eq!(
+            Command::decode(&[Command::SET_STATE, 1, 0x42]),
+            Err(ProtocolError::UnknownState(0x42))
+        );
+    }
+
+    #[test]
+    fn response_round_trips() {
+        let response = Response {
+            command: Command::GET_TIME,
+            payload: 1_700_000_000_000_000_u64.to_le_bytes().to_vec(),
+        };
+        let parsed = Response::parse(&Command::GetTime, &response.encode().unwrap()).unwrap();
+        assert_eq!(parsed, response);
+        assert_eq!(parsed.time(), Ok(1_700_000_000_000_000));
+
+        // Padding beyond the announced length is ignored
+        let mut padded = response.encode().unwrap();
+        padded.extend_from_slice(&[0; 4]);
+        assert_eq!(Response::parse(&Command::GetTime, &padded), Ok(response));
+    }
+
+    #[test]
+    fn rejects_short_reply() {
+        assert_eq!(
+            Response::parse(&Command::GetState, &[ACK, 2, Command::GET_STATE]),
+            Err(ProtocolError::Truncated {
+                expected: 4,
+                actual: 3
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_length_mismatch() {
+        assert_eq!(
+            Response::parse(&Command::GetState, &[ACK, 3, Command::GET_STATE, 0]),
+            Err(ProtocolError::Truncated {
+                expected: 5,
+                actual: 4
+            })
+        );
+        assert_eq!(
+            Response::parse(&Command::GetState, &[ACK, 1, Command::GET_STATE, 0]),
+            Err(ProtocolError::InvalidLength(1))
+        );
+        // A payload shorter than the reply requires
+        let response = Response::parse(&Command::GetTime, &[ACK, 4, Command::GET_TIME, 0, 1, 2]);
+        assert_eq!(
+            response.unwrap().time(),
+            Err(ProtocolError::Truncated {
+                expected: 12,
+                actual: 6
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_ack() {
+        assert_eq!(
+            Response::parse(&Command::GetState, &[0x01, 3, Command::GET_STATE, 0, 2]),
+            Err(ProtocolError::NotAcknowledged(0x01))
+        );
+        assert_eq!(
+            Response::parse(&Command::GetState, &[ACK, 3, Command::GET_TIME, 0, 2]),
+            Err(ProtocolError::UnexpectedCommand {
+                expected: Command::GET_STATE,
+                actual: Command::GET_TIME
+            })
+        );
+    }
+
+    #[test]
+    fn reports_device_error_code() {
+        let bytes = Response::encode_error(Command::ERASE_MEMORY, 0x05);
+        assert_eq!(
+            Response::parse(&Command::EraseMemory, &bytes),
+            Err(ProtocolError::Device(0x05))
+        );
+    }
+}
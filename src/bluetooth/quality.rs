@@ -0,0 +1,156 @@
+//! Signal strength and link performance of a mitch over time, to spot a bad placement of the
+//! adapters before a recording starts.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    buffer::Buffer,
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style},
+    text::Span,
+    widgets::{Block, BorderType, Sparkline, Widget, WidgetRef},
+};
+
+/// Number of values kept per history, one per advertisement or poll.
+const HISTORY: usize = 60;
+/// Signal strength in dBm at which a device counts as out of range, the bottom of the charts.
+const RSSI_FLOOR: i16 = -100;
+/// Lowest signal strength for each of the four signal bars, in dBm.
+const BAR_THRESHOLDS: [i16; 4] = [-90, -80, -70, -60];
+
+/// The recent signal strength, transmit power, throughput and latency of a mitch.
+#[derive(Clone, Debug, Default)]
+pub struct LinkQuality {
+    /// Received signal strength in dBm, from advertisements or, while connected, the adapter.
+    pub rssi: VecDeque<i16>,
+    /// Transmit power the device advertises, in dBm.
+    pub tx_power: VecDeque<i16>,
+    /// Stream notification payload received per second.
+    pub throughput: VecDeque<f64>,
+    /// Round trip of the state query made every poll.
+    pub latency: VecDeque<Duration>,
+    /// Payload counted by the running stream and when, the base of the next throughput value.
+    counted: Option<(Instant, u64)>,
+}
+
+impl LinkQuality {
+    /// Takes note of the signal strength and transmit power, each if known.
+    pub fn signal(&mut self, rssi: Option<i16>, tx_power: Option<i16>) {
+        if let Some(rssi) = rssi {
+            push(&mut self.rssi, rssi);
+        }
+        if let Some(tx_power) = tx_power {
+            push(&mut self.tx_power, tx_power);
+        }
+    }
+
+    pub fn latency(&mut self, round_trip: Duration) {
+        push(&mut self.latency, round_trip);
+    }
+
+    /// Derives the throughput from the `total` payload bytes received by the running stream,
+    /// `None` if no stream is running.
+    pub fn count(&mut self, total: Option<u64>) {
+        let now = Instant::now();
+        let Some(total) = total else {
+            self.counted = None;
+            return;
+        };
+        if let Some((then, before)) = self.counted {
+            let elapsed = now.duration_since(then).as_secs_f64();
+            if elapsed > 0.0 && total >= before {
+                push(&mut self.throughput, (total - before) as f64 / elapsed);
+            }
+        }
+        self.counted = Some((now, total));
+    }
+
+    /// The latest signal strength in dBm.
+    pub fn last_rssi(&self) -> Option<i16> {
+        self.rssi.back().copied()
+    }
+
+    /// Number of signal bars from 0 to 4 for the latest signal strength.
+    pub fn bars(&self) -> Option<usize> {
+        let rssi = self.last_rssi()?;
+        Some(BAR_THRESHOLDS.iter().filter(|&&t| rssi >= t).count())
+    }
+
+    /// Signal bars for the device list, colored by how usable the signal is.
+    pub fn bars_span(&self) -> Span<'static> {
+        let Some(bars) = self.bars() else {
+            return Span::styled("····", Style::default().fg(Color::DarkGray));
+        };
+        let text: String = "▂▄▆█"
+            .chars()
+            .enumerate()
+            .map(|(i, c)| if i < bars { c } else { ' ' })
+            .collect();
+        let color = match bars {
+            0 | 1 => Color::Red,
+            2 => Color::Yellow,
+            _ => Color::Green,
+        };
+        Span::styled(text, Style::default().fg(color))
+    }
+}
+
+/// Appends `value`, dropping the oldest one once the history is full.
+fn push<T>(history: &mut VecDeque<T>, value: T) {
+    if history.len() == HISTORY {
+        history.pop_front();
+    }
+    history.push_back(value);
+}
+
+/// One sparkline per history, side by side, each titled with its latest value.
+impl WidgetRef for LinkQuality {
+    fn render_ref(&self, area: Rect, buf: &mut Buffer) {
+        let above_floor = |v: &i16| (v - RSSI_FLOOR).max(0) as u64;
+        let charts = [
+            (
+                self.last_rssi()
+                    .map_or("rssi".to_string(), |v| format!("rssi {v} dBm")),
+                self.rssi.iter().map(above_floor).collect::<Vec<_>>(),
+            ),
+            (
+                self.tx_power
+                    .back()
+                    .map_or("tx power".to_string(), |v| format!("tx power {v} dBm")),
+                self.tx_power.iter().map(above_floor).collect(),
+            ),
+            (
+                self.throughput
+                    .back()
+                    .map_or("throughput".to_string(), |v| {
+                        format!("throughput {:.1} kB/s", v / 1000.0)
+                    }),
+                self.throughput.iter().map(|v| *v as u64).collect(),
+            ),
+            (
+                self.latency.back().map_or("latency".to_string(), |v| {
+                    format!("latency {:.1} ms", v.as_secs_f64() * 1000.0)
+                }),
+                self.latency.iter().map(|v| v.as_micros() as u64).collect(),
+            ),
+        ];
+        let areas = Layout::horizontal([Constraint::Ratio(1, 4); 4]).split(area);
+        for ((title, data), area) in charts.into_iter().zip(areas.iter()) {
+            // The newest values are at the right edge, older ones scroll out to the left
+            let width = area.width.saturating_sub(2) as usize;
+            let shown = &data[data.len().saturating_sub(width)..];
+            Sparkline::default()
+                .block(
+                    Block::bordered()
+                        .border_type(BorderType::Rounded)
+                        .title(title),
+                )
+                .style(Style::default().fg(Color::Cyan))
+                .data(shown)
+                .render(*area, buf);
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Download of log files recorded in [`MitchState::SysLog`](super::mitch::MitchState::SysLog).
+//!
+//! A log file is the sequence of frames recorded in its [`StreamConfig`], without packet
+//! headers. The raw bytes are first collected in a `.part` file next to the destination so an
+//! interrupted download resumes where it stopped. Once complete and matching the checksum
+//! reported by the device, the data is decoded and written as CSV. With a [`ClockSync`] of the
+//! device, every sample is also stamped on the LSL clock of the host.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{OptionExt, eyre};
+use futures::StreamExt;
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+    task::JoinHandle,
+};
+
+use super::{
+    clock::ClockSync,
+    mitch::{DATA_CHAR, send_command},
+    protocol::{Command, Response, StreamConfig},
+    recorder::file_stem,
+    transport::MitchTransport,
+};
+
+/// Directory the downloaded log files are written to.
+pub const READOUT_DIR: &str = "readouts";
+
+/// Time without progress after which a download is considered interrupted.
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time after requesting the rest of a file in which its first chunk is due, and without data
+/// after which the rest is requested again.
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A log file stored on the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogFile {
+    pub index: u8,
+    pub config: StreamConfig,
+    /// Size in bytes.
+    pub size: u32,
+    /// CRC-32 of the file content.
+    pub crc: u32,
+    /// Device time of the first sample in microseconds since the Unix epoch.
+    pub start: u64,
+}
+
+impl LogFile {
+    /// Parses the reply to [`Command::GetFileInfo`] for the file `index`.
+    pub fn parse(index: u8, response: &Response) -> color_eyre::Result<Self> {
+        let [mode, frequency, s0, s1, s2, s3, c0, c1, c2, c3, t @ ..] = response.take::<18>()?;
+        Ok(Self {
+            index,
+            config: StreamConfig::parse(mode, frequency)?,
+            size: u32::from_le_bytes([s0, s1, s2, s3]),
+            crc: u32::from_le_bytes([c0, c1, c2, c3]),
+            start: u64::from_le_bytes(t),
+        })
+    }
+}
+
+/// State of a [`ReadoutSession`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReadoutStatus {
+    Running {
+        received: u32,
+        size: u32,
+    },
+    /// The file passed the integrity check and was written to the path.
+    Verified(PathBuf),
+    Failed(String),
+}
+
+/// Handle to the task downloading a log file.
+pub struct ReadoutSession {
+    file: LogFile,
+    status: Arc<Mutex<ReadoutStatus>>,
+    task: JoinHandle<()>,
+}
+
+impl ReadoutSession {
+    /// Spawns the download of `file` into `dir`, resuming an earlier partial download.
+    pub fn start(
+        transport: Arc<dyn MitchTransport>,
+        name: &str,
+        file: LogFile,
+        clock: Option<ClockSync>,
+        dir: &Path,
+    ) -> Self {
+        let status = Arc::new(Mutex::new(ReadoutStatus::Running {
+            received: 0,
+            size: file.size,
+        }));
+        let destination = dir.join(format!("{}_{}.csv", file_stem(name), file.index));
+        let task_status = status.clone();
+        let task = tokio::spawn(async move {
+            let result =
+                download(transport.as_ref(), file, clock, &destination, &task_status).await;
+            *task_status.lock().unwrap() = match result {
+                Ok(()) => ReadoutStatus::Verified(destination),
+                Err(e) => ReadoutStatus::Failed(e.to_string()),
+            };
+        });
+        Self { file, status, task }
+    }
+
+    pub fn file(&self) -> LogFile {
+        self.file
+    }
+
+    pub fn status(&self) -> ReadoutStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+impl Drop for ReadoutSession {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn download(
+    transport: &dyn MitchTransport,
+    file: LogFile,
+    clock: Option<ClockSync>,
+    destination: &Path,
+    status: &Mutex<ReadoutStatus>,
+) -> color_eyre::Result<()> {
+    let part_path = destination.with_extension("part");
+    if let Some(dir) = part_path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    let mut received = fs::metadata(&part_path).await.map(|m| m.len()).unwrap_or(0) as u32;
+    if received > file.size {
+        // Left over from a different file with the same index
+        fs::remove_file(&part_path).await?;
+        received = 0;
+    }
+    let mut part = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&part_path)
+        .await?;
+
+    transport.subscribe(DATA_CHAR).await?;
+    let mut notifications = transport.notifications().await?;
+    let request = |offset| {
+        send_command(
+            transport,
+            Command::ReadFile {
+                file: file.index,
+                offset,
+            },
+        )
+    };
+    // When the rest of the file was requested again, until its first chunk arrives
+    let mut requested: Option<Instant> = None;
+    let mut progress = Instant::now();
+    if received < file.size {
+        request(received).await?;
+    }
+    while received < file.size {
+        let notification = match tokio::time::timeout(REQUEST_TIMEOUT, notifications.next()).await {
+            Ok(notification) => notification.ok_or_eyre("Connection lost during readout")?,
+            Err(_) if progress.elapsed() > STALL_TIMEOUT => {
+                return Err(eyre!(
+                    "Readout stalled at {received} of {} bytes",
+                    file.size
+                ));
+            }
+            // The end of the file went missing, no later chunk tells
+            Err(_) => {
+                request(received).await?;
+                continue;
+            }
+        };
+        if notification.uuid != DATA_CHAR || notification.value.len() < 4 {
+            continue;
+        }
+        let (offset, data) = notification.value.split_at(4);
+        let offset = u32::from_le_bytes([offset[0], offset[1], offset[2], offset[3]]);
+        if offset > received {
+            // A chunk went missing, request the rest again from where we are. Chunks already in
+            // flight miss as well, so only ask again once the first chunk of the last request is
+            // overdue.
+            if requested.is_some_and(|at| at.elapsed() < REQUEST_TIMEOUT) {
+                continue;
+            }
+            request(received).await?;
+            requested = Some(Instant::now());
+            continue;
+        }
+        // Skip the part we already have of a repeated chunk
+        let skip = (received - offset) as usize;
+        if skip >= data.len() {
+            continue;
+        }
+        let take = (data.len() - skip).min((file.size - received) as usize);
+        part.write_all(&data[skip..skip + take]).await?;
+        received += take as u32;
+        requested = None;
+        progress = Instant::now();
+        *status.lock().unwrap() = ReadoutStatus::Running {
+            received,
+            size: file.size,
+        };
+    }
+    part.flush().await?;
+    drop(part);
+
+    let raw = fs::read(&part_path).await?;
+    // Checking and decoding a whole log file takes a while, the other tasks must not wait for it
+    let (raw, crc) = tokio::task::spawn_blocking(move || {
+        let crc = crc32(&raw);
+        (raw, crc)
+    })
+    .await?;
+    if crc != file.crc {
+        fs::remove_file(&part_path).await?;
+        return Err(eyre!(
+            "Checksum mismatch: expected {:#010x}, got {crc:#010x}",
+            file.crc
+        ));
+    }
+    let csv = destination.to_path_buf();
+    tokio::task::spawn_blocking(move || write_csv(file, clock, &raw, &csv)).await??;
+    fs::remove_file(&part_path).await?;
+    Ok(())
+}
+
+/// Writes the decoded samples with their time since the start of the log and, if the clock of
+/// the device is known, their LSL timestamp.
+fn write_csv(
+    file: LogFile,
+    clock: Option<ClockSync>,
+    raw: &[u8],
+    destination: &Path,
+) -> color_eyre::Result<()> {
+    let config = file.config;
+    let start = clock.map(|c| c.to_lsl(file.start));
+    let samples = config.mode.decode_frames(raw)?;
+    let mut out = BufWriter::new(File::create(destination)?);
+    let labels: Vec<String> = config
+        .mode
+        .channels()
+        .into_iter()
+        .map(|c| c.label)
+        .collect();
+    writeln!(out, "time,timestamp,{}", labels.join(","))?;
+    for (i, sample) in samples.iter().enumerate() {
+        let time = i as f64 / config.frequency.hz();
+        let timestamp = start.map_or(String::new(), |s| format!("{:.6}", s + time));
+        let values: Vec<String> = sample.values().iter().map(|v| v.to_string()).collect();
+        writeln!(out, "{time:.4},{timestamp},{}", values.join(","))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// CRC-32 (IEEE 802.3) as used by the firmware for log files.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,92 @@
+//! Automatic reconnection of a mitch whose link dropped.
+
+use std::time::{Duration, Instant};
+
+/// How often and how patiently to try reaching a device again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// Delay before the first attempt, doubled for every further one.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub max_attempts: u32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            max_attempts: 10,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before the attempt numbered `attempt`, starting at 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
+    }
+}
+
+/// Progress of reconnecting after a link loss.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reconnect {
+    /// Waiting until `next` to make the attempt numbered `attempt`.
+    Waiting {
+        attempt: u32,
+        next: Instant,
+        last_error: String,
+    },
+    /// All attempts failed, the device stays disconnected until connected by hand.
+    GaveUp { attempts: u32, last_error: String },
+}
+
+impl Reconnect {
+    /// Schedules the first attempt after the link was lost with `error`.
+    pub fn start(policy: &ReconnectPolicy, error: String) -> Self {
+        Reconnect::Waiting {
+            attempt: 1,
+            next: Instant::now() + policy.delay(1),
+            last_error: error,
+        }
+    }
+
+    /// The state after the attempt numbered `attempt` failed with `error`.
+    pub fn failed(policy: &ReconnectPolicy, attempt: u32, error: String) -> Self {
+        if attempt >= policy.max_attempts {
+            return Reconnect::GaveUp {
+                attempts: attempt,
+                last_error: error,
+            };
+        }
+        Reconnect::Waiting {
+            attempt: attempt + 1,
+            next: Instant::now() + policy.delay(attempt + 1),
+            last_error: error,
+        }
+    }
+
+    /// One line description for the UI.
+    pub fn describe(&self, policy: &ReconnectPolicy) -> String {
+        match self {
+            Reconnect::Waiting {
+                attempt,
+                next,
+                last_error,
+            } => format!(
+                "reconnect {attempt}/{} in {}s ({last_error})",
+                policy.max_attempts,
+                next.saturating_duration_since(Instant::now())
+                    .as_secs_f64()
+                    .ceil()
+            ),
+            Reconnect::GaveUp {
+                attempts,
+                last_error,
+            } => format!("link lost, gave up after {attempts} attempts ({last_error})"),
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Recording of the decoded samples of a stream to CSV, for quick tests without an LSL recorder.
+//!
+//! Every recording of a device gets a file of its own in [`RECORDING_DIR`], named after the
+//! device and the time it started. Next to the values of the channels, each row holds the LSL
+//! timestamp the sample is pushed with, its device time, its nominal time and the counter of
+//! the packet it arrived in. The nominal time is the index of the sample over the nominal
+//! sampling rate. The device time adds it to the time of the first sample on the device clock,
+//! in seconds since the Unix epoch, and stays empty for firmware that does not report it. Rows
+//! are collected in memory and written out in batches, once the recording stops at the latest.
+
+use std::{
+    fmt::Write as _,
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+};
+
+use super::protocol::StreamConfig;
+
+/// Directory the recordings are written to.
+pub const RECORDING_DIR: &str = "recordings";
+
+/// Size in bytes the collected rows reach before [`CsvRecorder::write_batch`] writes them out.
+const BATCH_LEN: usize = 64 * 1024;
+
+/// `name` made safe to use in a file name, with path separators, dots and other characters
+/// not allowed everywhere replaced by `_`.
+pub fn file_stem(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() || c == '-' || c == '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// An open CSV recording.
+pub struct CsvRecorder {
+    path: PathBuf,
+    file: File,
+    /// Rows not written to the file yet.
+    rows: String,
+    /// Nominal sampling rate, the nominal time advances by its period from sample to sample.
+    rate: f64,
+    /// Device time of the first sample in microseconds since the Unix epoch, if reported.
+    device_start: Option<u64>,
+}
+
+impl CsvRecorder {
+    /// Creates the file for a recording of the device `name` with `config` in `dir` and writes
+    /// the header row.
+    pub async fn create(dir: &Path, name: &str, config: StreamConfig) -> color_eyre::Result<Self> {
+        fs::create_dir_all(dir).await?;
+        let started = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = dir.join(format!("{}_{started}.csv", file_stem(name)));
+        let file = File::create_new(&path).await?;
+        let labels: Vec<String> = config
+            .mode
+            .channels()
+            .into_iter()
+            .map(|c| c.label)
+            .collect();
+        let rows = format!(
+            "host_time,device_time,nominal_time,counter,{}\n",
+            labels.join(",")
+        );
+        Ok(Self {
+            path,
+            file,
+            rows,
+            rate: config.frequency.hz(),
+            device_start: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Sets the device time of the first sample, as reported when the stream started.
+    pub fn set_device_start(&mut self, start: Option<u64>) {
+        self.device_start = start;
+    }
+
+    /// Adds the row of the sample with `index` that arrived in the packet with `counter` and is
+    /// stamped with the LSL time `host_time`.
+    ///
+    /// The row stays in memory until the next [`CsvRecorder::write_batch`].
+    pub fn add(&mut self, host_time: f64, index: u64, counter: u16, values: &[f64]) {
+        let nominal_time = index as f64 / self.rate;
+        // Writing to a string cannot fail
+        let _ = write!(self.rows, "{host_time:.6},");
+        if let Some(start) = self.device_start {
+            let micros = start + (nominal_time * 1e6).round() as u64;
+            let _ = write!(
+                self.rows,
+                "{}.{:06}",
+                micros / 1_000_000,
+                micros % 1_000_000
+            );
+        }
+        let _ = write!(self.rows, ",{nominal_time:.4},{counter}");
+        for value in values {
+            let _ = write!(self.rows, ",{value}");
+        }
+        self.rows.push('\n');
+    }
+
+    /// Writes out the collected rows once there are enough of them.
+    pub async fn write_batch(&mut self) -> io::Result<()> {
+        if self.rows.len() < BATCH_LEN {
+            return Ok(());
+        }
+        self.write_rows().await
+    }
+
+    async fn write_rows(&mut self) -> io::Result<()> {
+        let rows = std::mem::take(&mut self.rows);
+        self.file.write_all(rows.as_bytes()).await
+    }
+
+    /// Writes out the remaining rows and closes the file.
+    pub async fn finish(mut self) -> io::Result<()> {
+        self.write_rows().await?;
+        self.file.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::{protocol::Frequency, sample::StreamMode};
+
+    #[test]
+    fn file_stem_stays_in_directory() {
+        assert_eq!(file_stem("mitch-left_1"), "mitch-left_1");
+        assert_eq!(file_stem("../etc/passwd"), "___etc_passwd");
+        assert_eq!(file_stem(r"C:\left foot"), "C__left_foot");
+        assert_eq!(file_stem("Fuß links"), "Fuß_links");
+    }
+
+    #[tokio::test]
+    async fn writes_device_and_nominal_time() {
+        let dir = std::env::temp_dir().join(format!("mitchrs-csv-{}", std::process::id()));
+        let config = StreamConfig {
+            mode: StreamMode::Orientation,
+            frequency: Frequency::Hz100,
+        };
+        let mut csv = CsvRecorder::create(&dir, "mitch-test", config)
+            .await
+            .unwrap();
+        csv.add(1.0, 0, 7, &[1.0, 0.0, 0.0, 0.0]);
+        csv.set_device_start(Some(1_700_000_000_999_990));
+        csv.add(1.5, 150, 9, &[1.0, 0.0, 0.0, 0.0]);
+        let path = csv.path().to_path_buf();
+        csv.finish().await.unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "host_time,device_time,nominal_time,counter,QuatW,QuatX,QuatY,QuatZ",
+                "1.000000,,0.0000,7,1,0,0,0",
+                "1.500000,1700000002.499990,1.5000,9,1,0,0,0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! Devices known from earlier sessions.
+//!
+//! The registry lives in `mitchrs/devices.toml` in the user config directory, with one table per
+//! device keyed by its address:
+//!
+//! ```toml
+//! [devices."C4:64:E3:0A:1B:2C"]
+//! alias = "anna left"
+//! role = "left-foot"
+//! subject = "anna"
+//! fill_gaps = true
+//! config = { mode = "PressureImu", frequency = "Hz100" }
+//! ```
+//!
+//! The discovery applies the entry of a known device before announcing it, the app writes the
+//! current settings of a device back on request. The file may as well be edited by hand while
+//! the app is not running, which is the only way to give a device an alias or a role. Writing
+//! back a paired insole sets its role to the foot it is paired as, a role off the feet is kept.
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::eyre::{OptionExt, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use super::{protocol::StreamConfig, subject::Side};
+
+/// Name of the registry file in the config directory of the app.
+const REGISTRY_FILE: &str = "devices.toml";
+
+/// Where the device is worn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    LeftFoot,
+    RightFoot,
+    LeftWrist,
+    RightWrist,
+    Trunk,
+}
+
+impl Role {
+    /// The side of a subject an insole with this role is paired as, `None` off the feet.
+    pub fn side(&self) -> Option<Side> {
+        match self {
+            Role::LeftFoot => Some(Side::Left),
+            Role::RightFoot => Some(Side::Right),
+            _ => None,
+        }
+    }
+
+    /// The role of an insole paired as `side`.
+    pub fn foot(side: Side) -> Self {
+        match side {
+            Side::Left => Role::LeftFoot,
+            Side::Right => Role::RightFoot,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Role::LeftFoot => "left foot",
+            Role::RightFoot => "right foot",
+            Role::LeftWrist => "left wrist",
+            Role::RightWrist => "right wrist",
+            Role::Trunk => "trunk",
+        };
+        f.write_str(text)
+    }
+}
+
+/// What is remembered of a device, everything left out keeps its default.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceEntry {
+    /// Name shown and announced instead of the advertised one.
+    pub alias: Option<String>,
+    pub role: Option<Role>,
+    /// Name of the subject wearing the device. Two insoles of the same subject with the left
+    /// and right foot as role are paired once both are discovered.
+    pub subject: Option<String>,
+    /// Stream configuration for recording and logging.
+    pub config: Option<StreamConfig>,
+    /// Whether recordings replace lost packets by NaN samples.
+    pub fill_gaps: Option<bool>,
+    /// Whether recordings also write the samples to CSV.
+    pub csv: Option<bool>,
+}
+
+/// Layout of the registry file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RegistryFile {
+    #[serde(default)]
+    devices: BTreeMap<String, DeviceEntry>,
+}
+
+/// The known devices by address, shared by the discovery and the app.
+///
+/// A registry without a file, like the default one, only lasts as long as the app.
+#[derive(Clone, Debug, Default)]
+pub struct Registry {
+    path: Option<PathBuf>,
+    devices: Arc<Mutex<BTreeMap<String, DeviceEntry>>>,
+}
+
+impl Registry {
+    /// Loads the registry from the user config directory, starting empty if there is none yet.
+    pub fn load() -> color_eyre::Result<Self> {
+        let dir = dirs::config_dir().ok_or_eyre("No config directory for the device registry")?;
+        Self::load_from(dir.join("mitchrs").join(REGISTRY_FILE))
+    }
+
+    /// Loads the registry from `path`, starting empty if the file does not exist yet.
+    pub fn load_from(path: PathBuf) -> color_eyre::Result<Self> {
+        let file = match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).wrap_err_with(|| {
+                format!("Reading the device registry {} failed", path.display())
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RegistryFile::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: Some(path),
+            devices: Arc::new(Mutex::new(file.devices)),
+        })
+    }
+
+    /// The entry of the device with `address`, if it is known.
+    pub fn get(&self, address: &str) -> Option<DeviceEntry> {
+        self.devices.lock().unwrap().get(address).cloned()
+    }
+
+    /// Replaces the entry of the device with `address` and writes the registry to its file.
+    pub fn remember(&self, address: &str, entry: DeviceEntry) -> color_eyre::Result<()> {
+        let mut devices = self.devices.lock().unwrap();
+        devices.insert(address.to_string(), entry);
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let file = RegistryFile {
+            devices: devices.clone(),
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Written next to the registry first, so a failure cannot leave it half written
+        let partial = path.with_extension("toml.part");
+        fs::write(&partial, toml::to_string_pretty(&file)?)?;
+        fs::rename(&partial, path)
+            .wrap_err_with(|| format!("Writing the device registry {} failed", path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::{protocol::Frequency, sample::StreamMode};
+
+    /// A registry file in a fresh temporary directory named after `test`.
+    fn path(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mitchrs-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join(REGISTRY_FILE)
+    }
+
+    fn entry(alias: &str) -> DeviceEntry {
+        DeviceEntry {
+            alias: Some(alias.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn entry_round_trip() {
+        let entry = DeviceEntry {
+            alias: Some("anna left".to_string()),
+            role: Some(Role::LeftFoot),
+            subject: Some("anna".to_string()),
+            config: Some(StreamConfig {
+                mode: StreamMode::PressureImu,
+                frequency: Frequency::Hz100,
+            }),
+            fill_gaps: Some(true),
+            csv: Some(false),
+        };
+        let text = toml::to_string(&entry).unwrap();
+        assert!(text.contains("role = \"left-foot\""), "{text}");
+        assert_eq!(toml::from_str::<DeviceEntry>(&text).unwrap(), entry);
+        assert_eq!(
+            toml::from_str::<DeviceEntry>("").unwrap(),
+            DeviceEntry::default()
+        );
+    }
+
+    #[test]
+    fn remember_replaces_one_entry() {
+        let path = path("registry-remember");
+        let registry = Registry::load_from(path.clone()).unwrap();
+        registry.remember("00:01", entry("one")).unwrap();
+        registry.remember("00:02", entry("two")).unwrap();
+        registry.remember("00:01", entry("uno")).unwrap();
+
+        let loaded = Registry::load_from(path.clone()).unwrap();
+        assert_eq!(loaded.get("00:01"), Some(entry("uno")));
+        assert_eq!(loaded.get("00:02"), Some(entry("two")));
+        assert_eq!(loaded.get("00:03"), None);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn missing_file_is_empty_corrupt_file_fails() {
+        let path = path("registry-corrupt");
+        let registry = Registry::load_from(path.clone()).unwrap();
+        assert_eq!(registry.get("00:01"), None);
+
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "[devices.\"00:01\"]\nrole = \"left-hand\"\n").unwrap();
+        let error = Registry::load_from(path.clone()).unwrap_err();
+        assert!(error.to_string().contains("device registry"), "{error}");
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+//! Decoding of [`DATA_CHAR`](super::mitch::DATA_CHAR) notifications into typed samples.
+//!
+//! Every notification starts with a little endian `u16` packet counter followed by one or more
+//! frames. The layout of a frame depends on the active [`StreamMode`]. Pressure cells are single
+//! `u8` bytes, every other value is a little endian `i16`:
+//!
+//! | mode          | frame                                      | bytes |
+//! |---------------|--------------------------------------------|-------|
+//! | `Pressure`    | 16 pressure cells (`u8`)                   | 16    |
+//! | `Imu6`        | accelerometer xyz, gyroscope xyz           | 12    |
+//! | `Imu9`        | accelerometer, gyroscope, magnetometer xyz | 18    |
+//! | `PressureImu` | 16 pressure cells, accelerometer, gyro     | 28    |
+//! | `Orientation` | quaternion wxyz                            | 8     |
+//!
+//! This layout is unconfirmed, like most of the [protocol](super::protocol): no firmware
+//! reference is at hand for the header, the frames or the sensor ranges behind the scales below.
+//! The [simulator](super::sim) encodes the same layout, so decoding its streams proves nothing
+//! about real devices. Check the layout and the scales against the firmware before relying on the
+//! recorded units.
+
+use std::fmt;
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+
+/// Number of pressure cells in a mitch insole.
+pub const PRESSURE_CELLS: usize = 16;
+
+// Unconfirmed from here on, see the module documentation
+
+/// Size of the packet counter in front of the frames.
+const PACKET_HEADER_LEN: usize = 2;
+
+/// Accelerometer resolution in g per LSB, assuming the ±16 g range.
+const ACC_SCALE: f32 = 16.0 / 32768.0;
+/// Gyroscope resolution in deg/s per LSB, assuming the ±2000 deg/s range.
+const GYRO_SCALE: f32 = 2000.0 / 32768.0;
+/// Magnetometer resolution in µT per LSB.
+const MAG_SCALE: f32 = 0.15;
+/// Quaternion components are transmitted as Q14 fixed point.
+const QUAT_SCALE: f32 = 1.0 / 16384.0;
+
+/// The data the device sends while streaming.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum StreamMode {
+    Pressure = 0x01,
+    Imu6 = 0x02,
+    Imu9 = 0x03,
+    #[default]
+    PressureImu = 0x04,
+    Orientation = 0x05,
+}
+
+impl TryFrom<u8> for StreamMode {
+    type Error = color_eyre::eyre::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|m| *m as u8 == value)
+            .ok_or_else(|| eyre!("Unknown stream mode: {value}"))
+    }
+}
+
+impl StreamMode {
+    pub const ALL: [StreamMode; 5] = [
+        StreamMode::Pressure,
+        StreamMode::Imu6,
+        StreamMode::Imu9,
+        StreamMode::Orientation,
+        StreamMode::PressureImu,
+    ];
+
+    /// The next mode in [`StreamMode::ALL`], wrapping around at the end.
+    pub fn next(&self) -> Self {
+        let i = Self::ALL.iter().position(|m| m == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// Size of a single frame in bytes.
+    pub fn frame_len(&self) -> usize {
+        match self {
+            StreamMode::Pressure => PRESSURE_CELLS,
+            StreamMode::Imu6 => 12,
+            StreamMode::Imu9 => 18,
+            StreamMode::PressureImu => PRESSURE_CELLS + 12,
+            StreamMode::Orientation => 8,
+        }
+    }
+
+    /// Number of values in a decoded [`Sample`].
+    pub fn channel_count(&self) -> usize {
+        match self {
+            StreamMode::Pressure => PRESSURE_CELLS,
+            StreamMode::Imu6 => 6,
+            StreamMode::Imu9 => 9,
+            StreamMode::PressureImu => PRESSURE_CELLS + 6,
+            StreamMode::Orientation => 4,
+        }
+    }
+
+    /// Description of every value of a decoded [`Sample`], in the order of [`Sample::values`].
+    pub fn channels(&self) -> Vec<Channel> {
+        let mut channels = Vec::with_capacity(self.channel_count());
+        if matches!(self, StreamMode::Pressure | StreamMode::PressureImu) {
+            channels.extend(
+                (1..=PRESSURE_CELLS).map(|i| Channel::new(format!("P{i}"), "Pressure", "a.u.")),
+            );
+        }
+        let mut vector = |label: &str, kind: &'static str, unit: &'static str| {
+            for axis in ["X", "Y", "Z"] {
+                channels.push(Channel::new(format!("{label}{axis}"), kind, unit));
+            }
+        };
+        match self {
+            StreamMode::Imu6 | StreamMode::PressureImu => {
+                vector("Acc", "Acceleration", "g");
+                vector("Gyro", "AngularVelocity", "deg/s");
+            }
+            StreamMode::Imu9 => {
+                vector("Acc", "Acceleration", "g");
+                vector("Gyro", "AngularVelocity", "deg/s");
+                vector("Mag", "MagneticField", "uT");
+            }
+            StreamMode::Pressure | StreamMode::Orientation => {}
+        }
+        if *self == StreamMode::Orientation {
+            channels.extend(
+                ["W", "X", "Y", "Z"]
+                    .map(|c| Channel::new(format!("Quat{c}"), "Orientation", "normalized")),
+            );
+        }
+        channels
+    }
+
+    /// Decodes a data notification received while streaming in this mode.
+    pub fn decode(&self, bytes: &[u8]) -> Result<DataPacket, DecodeError> {
+        if bytes.len() < PACKET_HEADER_LEN {
+            return Err(DecodeError::Truncated(bytes.len()));
+        }
+        let frames = &bytes[PACKET_HEADER_LEN..];
+        if frames.is_empty() {
+            return Err(DecodeError::Misaligned {
+                len: 0,
+                frame_len: self.frame_len(),
+            });
+        }
+        Ok(DataPacket {
+            counter: u16::from_le_bytes([bytes[0], bytes[1]]),
+            samples: self.decode_frames(frames)?,
+        })
+    }
+
+    /// Decodes consecutive frames without packet header, as stored in log files.
+    pub fn decode_frames(&self, frames: &[u8]) -> Result<Vec<Sample>, DecodeError> {
+        if !frames.len().is_multiple_of(self.frame_len()) {
+            return Err(DecodeError::Misaligned {
+                len: frames.len(),
+                frame_len: self.frame_len(),
+            });
+        }
+        Ok(frames
+            .chunks_exact(self.frame_len())
+            .map(|frame| self.decode_frame(frame))
+            .collect())
+    }
+
+    fn decode_frame(&self, frame: &[u8]) -> Sample {
+        let mut sample = Sample::default();
+        let imu = match self {
+            StreamMode::Pressure => {
+                sample.pressure = Some(pressure(frame));
+                return sample;
+            }
+            StreamMode::Orientation => {
+                sample.orientation = Some(vector(frame, QUAT_SCALE));
+                return sample;
+            }
+            StreamMode::PressureImu => {
+                sample.pressure = Some(pressure(frame));
+                &frame[PRESSURE_CELLS..]
+            }
+            StreamMode::Imu6 | StreamMode::Imu9 => frame,
+        };
+        sample.acc = Some(vector(&imu[0..6], ACC_SCALE));
+        sample.gyro = Some(vector(&imu[6..12], GYRO_SCALE));
+        if *self == StreamMode::Imu9 {
+            sample.mag = Some(vector(&imu[12..18], MAG_SCALE));
+        }
+        sample
+    }
+}
+
+fn pressure(frame: &[u8]) -> [u8; PRESSURE_CELLS] {
+    let mut cells = [0; PRESSURE_CELLS];
+    cells.copy_from_slice(&frame[..PRESSURE_CELLS]);
+    cells
+}
+
+fn vector<const N: usize>(bytes: &[u8], scale: f32) -> [f32; N] {
+    let mut v = [0.0; N];
+    for (value, raw) in v.iter_mut().zip(bytes.chunks_exact(2)) {
+        *value = i16::from_le_bytes([raw[0], raw[1]]) as f32 * scale;
+    }
+    v
+}
+
+/// Description of one value of a [`Sample`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Channel {
+    pub label: String,
+    /// Channel type as used in the XDF meta-data conventions.
+    pub kind: &'static str,
+    pub unit: &'static str,
+}
+
+impl Channel {
+    fn new(label: String, kind: &'static str, unit: &'static str) -> Self {
+        Self { label, kind, unit }
+    }
+}
+
+/// A single decoded measurement. Only the fields sent in the active [`StreamMode`] are set.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sample {
+    /// Raw pressure cell readings.
+    pub pressure: Option<[u8; PRESSURE_CELLS]>,
+    /// Acceleration in g.
+    pub acc: Option<[f32; 3]>,
+    /// Angular rate in deg/s.
+    pub gyro: Option<[f32; 3]>,
+    /// Magnetic field in µT.
+    pub mag: Option<[f32; 3]>,
+    /// Orientation as unit quaternion (w, x, y, z).
+    pub orientation: Option<[f32; 4]>,
+}
+
+impl Sample {
+    /// Flattens the sample into one value per channel, in the order pressure, accelerometer,
+    /// gyroscope, magnetometer, orientation.
+    pub fn values(&self) -> Vec<f64> {
+        let mut values = Vec::new();
+        if let Some(pressure) = self.pressure {
+            values.extend(pressure.iter().map(|&p| p as f64));
+        }
+        for v in [self.acc, self.gyro, self.mag].into_iter().flatten() {
+            values.extend(v.iter().map(|&x| x as f64));
+        }
+        if let Some(q) = self.orientation {
+            values.extend(q.iter().map(|&x| x as f64));
+        }
+        values
+    }
+}
+
+/// All samples carried by one data notification.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataPacket {
+    /// Packet counter, incremented by the device for every notification.
+    pub counter: u16,
+    pub samples: Vec<Sample>,
+}
+
+/// Reasons a data notification could not be decoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The notification is too short to hold the packet counter.
+    Truncated(usize),
+    /// The frames do not fit the frame size of the stream mode.
+    Misaligned { len: usize, frame_len: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated(len) => write!(f, "data packet truncated to {len} bytes"),
+            DecodeError::Misaligned { len, frame_len } => write!(
+                f,
+                "{len} bytes of frame data do not fit frames of {frame_len} bytes"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A packet with `counter` and `frames` frames of `mode` filled with `fill`.
+    fn packet(mode: StreamMode, counter: u16, frames: usize, fill: u8) -> Vec<u8> {
+        let mut bytes = counter.to_le_bytes().to_vec();
+        bytes.resize(PACKET_HEADER_LEN + frames * mode.frame_len(), fill);
+        bytes
+    }
+
+    #[test]
+    fn decodes_frames_of_every_mode() {
+        let frame_lens = [
+            (StreamMode::Pressure, 16),
+            (StreamMode::Imu6, 12),
+            (StreamMode::Imu9, 18),
+            (StreamMode::PressureImu, 28),
+            (StreamMode::Orientation, 8),
+        ];
+        for (mode, frame_len) in frame_lens {
+            assert_eq!(mode.frame_len(), frame_len, "{mode:?}");
+            let decoded = mode.decode(&packet(mode, 0x1234, 3, 1)).unwrap();
+            assert_eq!(decoded.counter, 0x1234, "{mode:?}");
+            assert_eq!(decoded.samples.len(), 3, "{mode:?}");
+            for sample in decoded.samples {
+                assert_eq!(sample.values().len(), mode.channel_count(), "{mode:?}");
+            }
+            assert_eq!(mode.channels().len(), mode.channel_count(), "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn scales_imu_values() {
+        let mut bytes = vec![7, 0];
+        for raw in [2048_i16, -2048, 0, 16384, -16384, 1] {
+            bytes.extend_from_slice(&raw.to_le_bytes());
+        }
+        let decoded = StreamMode::Imu6.decode(&bytes).unwrap();
+        assert_eq!(decoded.counter, 7);
+        assert_eq!(
+            decoded.samples,
+            vec![Sample {
+                acc: Some([1.0, -1.0, 0.0]),
+                gyro: Some([1000.0, -1000.0, GYRO_SCALE]),
+                ..Sample::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        for mode in StreamMode::ALL {
+            assert_eq!(mode.decode(&[0x12]), Err(DecodeError::Truncated(1)));
+            let frame_len = mode.frame_len();
+            assert_eq!(
+                mode.decode(&[0x12, 0x34]),
+                Err(DecodeError::Misaligned { len: 0, frame_len })
+            );
+            // The last frame lost its final byte
+            let mut bytes = packet(mode, 1, 2, 0);
+            bytes.pop();
+            assert_eq!(
+                mode.decode(&bytes),
+                Err(DecodeError::Misaligned {
+                    len: 2 * frame_len - 1,
+                    frame_len
+                })
+            );
+        }
+    }
+}
@@ -0,0 +1,656 @@
+//! A running LSL stream of a single mitch.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use color_eyre::eyre::eyre;
+use futures::StreamExt;
+use lsl::Pushable;
+use tokio::{select, sync::oneshot, task::JoinHandle};
+
+use super::{
+    info::DeviceInfo,
+    mitch::DATA_CHAR,
+    outlet::{self, SendOutlet},
+    protocol::StreamConfig,
+    recorder::CsvRecorder,
+    sample::DataPacket,
+    subject::{PairStream, Side},
+    timing::TimingEstimator,
+    transport::Notifications,
+};
+use crate::xdf::{XdfRecorder, XdfStream};
+
+/// Longest silence of the device that is filled in, a longer jump of the packet counter is
+/// taken as a resync.
+const MAX_GAP: Duration = Duration::from_secs(5);
+
+/// Counters of a [`StreamSession`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StreamStats {
+    /// Data notifications received.
+    pub packets: u64,
+    /// Payload of the data notifications in bytes.
+    pub bytes: u64,
+    /// Samples pushed to the outlet.
+    pub samples: u64,
+    /// Notifications that could not be decoded.
+    pub malformed: u64,
+    /// Packets skipped by the packet counter.
+    pub missing: u64,
+    /// Packets that arrived after a later one, or twice. They are dropped.
+    pub out_of_order: u64,
+    /// Jumps of the packet counter too far to be a gap, no packets are counted missing for them.
+    pub resyncs: u64,
+    /// Drift of the device sample clock against the host, see [`TimingEstimator::drift_ppm`].
+    pub drift_ppm: f64,
+    /// Spread of the packet arrival times in seconds, see [`TimingEstimator::jitter`].
+    pub jitter: f64,
+}
+
+impl StreamStats {
+    /// Share of the packets sent by the device that never arrived.
+    pub fn loss_rate(&self) -> f64 {
+        let sent = self.packets - self.malformed - self.out_of_order + self.missing;
+        if sent == 0 {
+            return 0.0;
+        }
+        self.missing as f64 / sent as f64
+    }
+}
+
+/// Where the samples of a session go besides its own outlet.
+#[derive(Default)]
+pub struct Sinks {
+    /// The combined stream of the subject, if the mitch is one side of a pair publishing one.
+    pub pair: Option<(Side, Arc<PairStream>)>,
+    /// File recording the samples.
+    pub csv: Option<CsvRecorder>,
+    /// Recording of all streams, the samples go there while it records.
+    pub xdf: XdfRecorder,
+}
+
+/// Handle to the task forwarding the data notifications of a mitch to its LSL outlet.
+///
+/// Sample timestamps are rebuilt from the sample index: from the LSL time of the first sample
+/// once [`StreamSession::anchor`] provides it, advancing by the sample period the
+/// [`TimingEstimator`] measures; until then the samples are held back. A refined period only
+/// applies to the samples not stamped yet, see [`SampleClock`]. Without a first sample
+/// time, the clock starts on the line fitted through the packet arrivals. Missing packets are
+/// detected from the packet counter and either left out or, with `fill_gaps`, replaced by NaN
+/// samples. Either way the samples after a gap keep their timestamps. A counter jumping further
+/// than [`MAX_GAP`] is more likely corrupt than the link that silent, counting resumes from it
+/// without filling anything in. The sample index then moves on by the time since the last
+/// packet, so the samples after a silence are not stamped right after the ones before it.
+///
+/// The samples also go to the [`Sinks`] of the session. Samples still held back when the session
+/// stops are stamped on arrival, so the recording keeps them.
+///
+/// The task is stopped by [`StreamSession::stop`] or, if the session is dropped without being
+/// stopped, aborted.
+pub struct StreamSession {
+    config: StreamConfig,
+    outlet: Arc<SendOutlet>,
+    pair: Option<(Side, Arc<PairStream>)>,
+    /// File the samples are recorded to.
+    csv: Option<PathBuf>,
+    stats: Arc<Mutex<StreamStats>>,
+    stop: Option<oneshot::Sender<()>>,
+    anchor: Option<oneshot::Sender<(Option<f64>, Option<u64>)>>,
+    task: JoinHandle<color_eyre::Result<()>>,
+}
+
+impl StreamSession {
+    /// Creates the outlet for `config` and spawns the task pushing `notifications` into it and
+    /// into `sinks`.
+    pub fn start(
+        name: &str,
+        address: &str,
+        device: &DeviceInfo,
+        config: StreamConfig,
+        fill_gaps: bool,
+        sinks: Sinks,
+        mut notifications: Notifications,
+    ) -> color_eyre::Result<Self> {
+        let info = outlet::stream_info(name, address, device, config)?;
+        let outlet = Arc::new(SendOutlet::new(&info)?);
+        let Sinks { pair, csv, xdf } = sinks;
+        let xdf = xdf.stream(info.to_xml()?);
+        if let Some((side, stream)) = &pair {
+            stream.attach(*side, config)?;
+        }
+        let csv_path = csv.as_ref().map(|c| c.path().to_path_buf());
+        let stats = Arc::new(Mutex::new(StreamStats::default()));
+        let (stop, mut stopped) = oneshot::channel();
+        let (anchor, mut anchored) = oneshot::channel::<(Option<f64>, Option<u64>)>();
+
+        let mut forwarder =
+            Forwarder::new(outlet.clone(), pair.clone(), csv, xdf, config, fill_gaps);
+        let task_stats = stats.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                select! {
+                    _ = &mut stopped => break,
+                    first = &mut anchored, if forwarder.start.is_none() => {
+                        let (first, device_start) = first.unwrap_or((None, None));
+                        forwarder.anchor(first, device_start, &mut task_stats.lock().unwrap());
+                        forwarder.write_csv().await;
+                    }
+                    n = notifications.next() => {
+                        let Some(n) = n else {
+                            break;
+                        };
+                        let arrival = lsl::local_clock();
+                        if n.uuid != DATA_CHAR {
+                            continue;
+                        }
+                        {
+                            let mut stats = task_stats.lock().unwrap();
+                            stats.packets += 1;
+                            stats.bytes += n.value.len() as u64;
+                            match config.mode.decode(&n.value) {
+                                Ok(packet) => forwarder.packet(packet, arrival, &mut stats),
+                                // Malformed packets are dropped, there is nothing to push for them
+                                Err(_) => stats.malformed += 1,
+                            }
+                        }
+                        // The file is written without holding up readers of the stats
+                        forwarder.write_csv().await;
+                    }
+                }
+            }
+            forwarder.flush_held_back(&mut task_stats.lock().unwrap());
+            forwarder.finish().await
+        });
+
+        Ok(Self {
+            config,
+            outlet,
+            pair,
+            csv: csv_path,
+            stats,
+            stop: Some(stop),
+            anchor: Some(anchor),
+            task,
+        })
+    }
+
+    /// Sets the LSL time of the first sample, or `None` to stamp the samples on arrival, and
+    /// its device time in microseconds since the Unix epoch if the device reported it.
+    ///
+    /// Only the first call has an effect.
+    pub fn anchor(&mut self, first_sample: Option<f64>, device_start: Option<u64>) {
+        if let Some(anchor) = self.anchor.take() {
+            let _ = anchor.send((first_sample, device_start));
+        }
+    }
+
+    pub fn config(&self) -> StreamConfig {
+        self.config
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        *self.stats.lock().unwrap()
+    }
+
+    /// The file the samples are recorded to, if any.
+    pub fn csv(&self) -> Option<&Path> {
+        self.csv.as_deref()
+    }
+
+    /// Whether any LSL consumer is connected to the outlet.
+    pub fn has_consumers(&self) -> bool {
+        self.outlet.have_consumers()
+    }
+
+    /// Stops the streaming task and waits until it released the outlet and completed the
+    /// recording.
+    ///
+    /// Fails if the recording could not be written.
+    pub async fn stop(mut self) -> color_eyre::Result<()> {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        // The task never panics, an error can only mean it was already aborted
+        (&mut self.task).await.unwrap_or(Ok(()))
+    }
+}
+
+/// State of the streaming task between notifications.
+struct Forwarder {
+    outlet: Arc<SendOutlet>,
+    pair: Option<(Side, Arc<PairStream>)>,
+    csv: Option<CsvRecorder>,
+    /// Why the recording ended early, it is given up on the first failed write.
+    csv_error: Option<String>,
+    xdf: XdfStream,
+    channels: usize,
+    fill_gaps: bool,
+    /// Nominal sampling rate.
+    rate: f64,
+    /// Timestamps from the LSL time of the first sample on, `Some(None)` if the device did not
+    /// report it until the first samples are stamped on arrival.
+    start: Option<Option<SampleClock>>,
+    timing: TimingEstimator,
+    /// Samples with their index and packet counter not pushed yet.
+    pending: Vec<(u64, u16, Vec<f64>)>,
+    counter: PacketCounter,
+    /// Index of the next sample in the stream, counting the ones lost in gaps.
+    index: u64,
+    /// LSL time the last packet arrived at.
+    last_arrival: Option<f64>,
+}
+
+impl Forwarder {
+    fn new(
+        outlet: Arc<SendOutlet>,
+        pair: Option<(Side, Arc<PairStream>)>,
+        csv: Option<CsvRecorder>,
+        xdf: XdfStream,
+        config: StreamConfig,
+        fill_gaps: bool,
+    ) -> Self {
+        Self {
+            outlet,
+            pair,
+            csv,
+            csv_error: None,
+            xdf,
+            channels: config.mode.channel_count(),
+            fill_gaps,
+            rate: config.frequency.hz(),
+            start: None,
+            timing: TimingEstimator::new(config.frequency.hz()),
+            pending: Vec::new(),
+            counter: PacketCounter::default(),
+            index: 0,
+            last_arrival: None,
+        }
+    }
+
+    fn anchor(&mut self, first: Option<f64>, device_start: Option<u64>, stats: &mut StreamStats) {
+        self.start = Some(first.map(|first| SampleClock::new(first, self.timing.period())));
+        if let Some(csv) = &mut self.csv {
+            csv.set_device_start(device_start);
+        }
+        self.flush(stats);
+    }
+
+    /// Handles a packet that arrived at the LSL time `arrival`.
+    fn packet(&mut self, packet: DataPacket, arrival: f64, stats: &mut StreamStats) {
+        let per_packet = packet.samples.len() as u64;
+        let packet_rate = self.rate / per_packet.max(1) as f64;
+        let max_gap = (MAX_GAP.as_secs_f64() * packet_rate).min(0x7FFF as f64) as u16;
+        let ahead = match self.counter.arrived(packet.counter, max_gap) {
+            Arrival::Next(ahead) => ahead,
+            Arrival::Late => {
+                stats.out_of_order += 1;
+                return;
+            }
+            Arrival::Resync => {
+                stats.resyncs += 1;
+                // The counter tells nothing of the time that passed, the arrival does
+                let elapsed = self.last_arrival.map_or(0.0, |last| arrival - last);
+                self.index += ((elapsed * self.rate).round() as u64).saturating_sub(per_packet);
+                0
+            }
+        };
+        self.last_arrival = Some(arrival);
+        let first_missing = packet.counter.wrapping_sub(ahead);
+        stats.missing += ahead as u64;
+        if self.fill_gaps {
+            for missing in 0..ahead {
+                for _ in 0..per_packet {
+                    let counter = first_missing.wrapping_add(missing);
+                    self.pending
+                        .push((self.index, counter, vec![f64::NAN; self.channels]));
+                    self.index += 1;
+                }
+            }
+        } else {
+            self.index += ahead as u64 * per_packet;
+        }
+        if packet.samples.is_empty() {
+            return;
+        }
+        for sample in packet.samples {
+            self.pending
+                .push((self.index, packet.counter, sample.values()));
+            self.index += 1;
+        }
+        // The packet is sent right after its last sample was taken
+        self.timing.observe(self.index - 1, arrival);
+        stats.drift_ppm = self.timing.drift_ppm();
+        stats.jitter = self.timing.jitter();
+        if self.start.is_some() {
+            self.flush(stats);
+        }
+    }
+
+    /// Pushes the pending samples as one chunk.
+    fn flush(&mut self, stats: &mut StreamStats) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let mut indices = Vec::with_capacity(self.pending.len());
+        let mut counters = Vec::with_capacity(self.pending.len());
+        let mut samples = Vec::with_capacity(self.pending.len());
+        for (index, counter, sample) in std::mem::take(&mut self.pending) {
+            indices.push(index);
+            counters.push(counter);
+            samples.push(sample);
+        }
+        let period = self.timing.period();
+        match &mut self.start {
+            Some(Some(clock)) => clock.set_period(indices[0], period),
+            Some(None) => {
+                let first = self
+                    .timing
+                    .host_time(indices[0])
+                    .unwrap_or_else(lsl::local_clock);
+                self.start = Some(Some(SampleClock::starting_at(indices[0], first, period)));
+            }
+            None => {}
+        }
+        let stamps: Vec<f64> = indices.iter().map(|&i| self.stamp(i)).collect();
+        // Pushing only fails for a wrong channel count, which is derived from the same mode as
+        // the decoder
+        let _ = self.outlet.push_chunk_stamped(&samples, &stamps);
+        stats.samples += samples.len() as u64;
+        self.xdf.push(&samples, &stamps);
+        if let Some(csv) = &mut self.csv {
+            for i in 0..samples.len() {
+                csv.add(stamps[i], indices[i], counters[i], &samples[i]);
+            }
+        }
+        if let Some((side, stream)) = &self.pair {
+            let stamped: Vec<(f64, Vec<f64>)> = stamps.into_iter().zip(samples).collect();
+            stream.push(*side, &stamped);
+        }
+    }
+
+    /// Writes the rows collected for the CSV file once there are enough of them, giving up the
+    /// recording on the first failed write.
+    async fn write_csv(&mut self) {
+        let Some(csv) = &mut self.csv else {
+            return;
+        };
+        if let Err(e) = csv.write_batch().await {
+            self.csv_error = Some(format!("Recording to {} failed: {e}", csv.path().display()));
+            self.csv = None;
+        }
+    }
+
+    /// Pushes the samples still held back, stamped on arrival if the first sample time never
+    /// came.
+    fn flush_held_back(&mut self, stats: &mut StreamStats) {
+        if self.start.is_none() {
+            self.start = Some(None);
+        }
+        self.flush(stats);
+    }
+
+    /// Completes the recording.
+    async fn finish(&mut self) -> color_eyre::Result<()> {
+        if let Some(error) = self.csv_error.take() {
+            return Err(eyre!(error));
+        }
+        if let Some(csv) = self.csv.take() {
+            let path = csv.path().to_path_buf();
+            csv.finish()
+                .await
+                .map_err(|e| eyre!("Recording to {} failed: {e}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// LSL timestamp of the sample with `index`, once the clock of the stream started.
+    fn stamp(&self, index: u64) -> f64 {
+        self.start
+            .flatten()
+            .map_or_else(lsl::local_clock, |clock| clock.stamp(index))
+    }
+}
+
+/// Maps sample indices onto the LSL clock, advancing by the estimated sample period.
+///
+/// Every update of the estimate would move all later samples by their index times the change,
+/// which adds up to seconds over a long recording and may even step back between two chunks.
+/// So a new period only applies from the first sample not stamped yet on.
+#[derive(Clone, Copy, Debug)]
+struct SampleClock {
+    /// Index and LSL time of the sample the period is counted from.
+    anchor: (u64, f64),
+    period: f64,
+}
+
+impl SampleClock {
+    /// Starts at the LSL time `first` of the sample with index 0.
+    fn new(first: f64, period: f64) -> Self {
+        Self::starting_at(0, first, period)
+    }
+
+    /// Starts at the LSL time `time` of the sample with `index`.
+    fn starting_at(index: u64, time: f64, period: f64) -> Self {
+        Self {
+            anchor: (index, time),
+            period,
+        }
+    }
+
+    /// Continues with `period` from the sample with `index` on, none after it is stamped yet.
+    fn set_period(&mut self, index: u64, period: f64) {
+        if period != self.period {
+            self.anchor = (index, self.stamp(index));
+            self.period = period;
+        }
+    }
+
+    fn stamp(&self, index: u64) -> f64 {
+        let (anchor_index, anchor_time) = self.anchor;
+        anchor_time + (index as f64 - anchor_index as f64) * self.period
+    }
+}
+
+/// Follows the packet counter of a stream to tell lost packets from late ones.
+#[derive(Debug, Default)]
+struct PacketCounter {
+    /// Counter of the next packet, `None` until the first one arrived. The device does not
+    /// necessarily start counting at zero, and a resumed stream starts a new session.
+    expected: Option<u16>,
+}
+
+/// How a packet follows the ones before it, see [`PacketCounter::arrived`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Arrival {
+    /// The next packet, after the given number of missing ones.
+    Next(u16),
+    /// A packet already given up as missing, or a repeated one.
+    Late,
+    /// The counter jumped too far to tell, counting goes on from it.
+    Resync,
+}
+
+impl PacketCounter {
+    /// Takes note of the packet with `counter`, given that no more than `max_gap` packets go
+    /// missing in a row. `max_gap` must stay below `0x8000`.
+    fn arrived(&mut self, counter: u16, max_gap: u16) -> Arrival {
+        let ahead = counter.wrapping_sub(self.expected.unwrap_or(counter));
+        let arrival = if ahead <= max_gap {
+            Arrival::Next(ahead)
+        } else if ahead.wrapping_neg() <= max_gap {
+            // Behind the expected counter, the packet was already given up as missing
+            return Arrival::Late;
+        } else {
+            Arrival::Resync
+        };
+        self.expected = Some(counter.wrapping_add(1));
+        arrival
+    }
+}
+
+impl Drop for StreamSession {
+    fn drop(&mut self) {
+        self.task.abort();
+        if let Some((side, stream)) = &self.pair {
+            stream.detach(*side);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::{protocol::Frequency, sample::StreamMode};
+
+    /// Packets allowed to go missing in a row in the counter tests.
+    const GAP: u16 = 100;
+
+    fn forwarder(config: StreamConfig) -> Forwarder {
+        let info =
+            outlet::stream_info("mitch-test", "test", &DeviceInfo::default(), config).unwrap();
+        let outlet = Arc::new(SendOutlet::new(&info).unwrap());
+        let xdf = XdfRecorder::default().stream(info.to_xml().unwrap());
+        Forwarder::new(outlet, None, None, xdf, config, false)
+    }
+
+    #[test]
+    fn resync_moves_on_by_the_silence() {
+        let config = StreamConfig {
+            mode: StreamMode::Imu6,
+            frequency: Frequency::Hz100,
+        };
+        let frames = vec![0; 2 * config.mode.frame_len()];
+        let packet = |counter| DataPacket {
+            counter,
+            samples: config.mode.decode_frames(&frames).unwrap(),
+        };
+        let mut forwarder = forwarder(config);
+        let mut stats = StreamStats::default();
+        forwarder.anchor(Some(1000.0), None, &mut stats);
+        // Two samples per packet, sent right after the second one was taken
+        for n in 0..500_u16 {
+            let arrival = 1000.0 + (2 * n + 1) as f64 * 0.01 + 0.005;
+            forwarder.packet(packet(n), arrival, &mut stats);
+        }
+        let before = forwarder.stamp(forwarder.index - 1);
+
+        // The device falls silent for 20 s and comes back with a counter from elsewhere
+        let arrival = before + 20.0 + 0.005;
+        forwarder.packet(packet(30_000), arrival, &mut stats);
+        assert_eq!(stats.resyncs, 1);
+        assert_eq!(stats.missing, 0);
+        let after = forwarder.stamp(forwarder.index - 1);
+        assert!(
+            (after - (before + 20.0)).abs() < 0.011,
+            "{before} -> {after}"
+        );
+        forwarder.packet(packet(30_001), arrival + 0.02, &mut stats);
+        assert!(stats.jitter < 0.005, "{}", stats.jitter);
+        assert!(stats.drift_ppm.abs() < 1.0, "{}", stats.drift_ppm);
+    }
+
+    #[test]
+    fn stamps_increase_while_period_is_refined() {
+        // A device sampling 900 ppm fast at 200 Hz: once the estimate leaves the nominal period,
+        // stamping every index with it would step back by more than a period
+        let rate = 200.0;
+        let period = (1.0 - 900e-6) / rate;
+        let mut timing = TimingEstimator::new(rate);
+        let mut clock = SampleClock::new(0.0, timing.period());
+        let mut last = None;
+        for packet in 0..30 * 50 {
+            let first = packet * 4;
+            let jitter = (packet % 7) as f64 * 1e-3;
+            timing.observe(first + 3, (first + 3) as f64 * period + 0.02 + jitter);
+            clock.set_period(first, timing.period());
+            for index in first..first + 4 {
+                let stamp = clock.stamp(index);
+                if let Some(last) = last {
+                    let step = stamp - last;
+                    assert!(step > 0.0 && step < 2.0 / rate, "step {step} at {index}");
+                }
+                last = Some(stamp);
+            }
+        }
+        assert_ne!(timing.period(), 1.0 / rate);
+    }
+
+    #[test]
+    fn stamps_increase_without_first_sample_time() {
+        let config = StreamConfig {
+            mode: StreamMode::Imu6,
+            frequency: Frequency::Hz200,
+        };
+        let frames = vec![0; 4 * config.mode.frame_len()];
+        let mut forwarder = forwarder(config);
+        let mut stats = StreamStats::default();
+        forwarder.anchor(None, None, &mut stats);
+        // The same fast device as above, the fitted line moves with every packet and most with
+        // the first ones
+        let period = (1.0 - 900e-6) / 200.0;
+        let mut last = None;
+        for n in 0..30 * 50_u16 {
+            let first = forwarder.index;
+            let packet = DataPacket {
+                counter: n,
+                samples: config.mode.decode_frames(&frames).unwrap(),
+            };
+            // The first packet is held up, as it often is right after subscribing
+            let jitter = if n == 0 { 0.03 } else { (n % 7) as f64 * 1e-3 };
+            let arrival = 1000.0 + (first + 3) as f64 * period + 0.02 + jitter;
+            forwarder.packet(packet, arrival, &mut stats);
+            for index in first..forwarder.index {
+                let stamp = forwarder.stamp(index);
+                if let Some(last) = last {
+                    let step = stamp - last;
+                    assert!(step > 0.0 && step < 2.0 / 200.0, "step {step} at {index}");
+                }
+                last = Some(stamp);
+            }
+        }
+        assert_eq!(stats.samples, 4 * 30 * 50);
+    }
+
+    #[test]
+    fn counter_starts_at_first_packet() {
+        let mut counter = PacketCounter::default();
+        assert_eq!(counter.arrived(500, GAP), Arrival::Next(0));
+        assert_eq!(counter.arrived(501, GAP), Arrival::Next(0));
+        assert_eq!(counter.arrived(504, GAP), Arrival::Next(2));
+        assert_eq!(counter.arrived(503, GAP), Arrival::Late);
+    }
+
+    #[test]
+    fn counter_starts_in_upper_half() {
+        let mut counter = PacketCounter::default();
+        assert_eq!(counter.arrived(0x9000, GAP), Arrival::Next(0));
+        assert_eq!(counter.arrived(0x9001, GAP), Arrival::Next(0));
+        assert_eq!(counter.arrived(0x9001, GAP), Arrival::Late);
+        assert_eq!(counter.arrived(0x9003, GAP), Arrival::Next(1));
+    }
+
+    #[test]
+    fn counter_wraps_around() {
+        let mut counter = PacketCounter::default();
+        assert_eq!(counter.arrived(0xFFFE, GAP), Arrival::Next(0));
+        assert_eq!(counter.arrived(0x0001, GAP), Arrival::Next(2));
+        assert_eq!(counter.arrived(0xFFFF, GAP), Arrival::Late);
+    }
+
+    #[test]
+    fn counter_resyncs_on_jump_beyond_gap() {
+        let mut counter = PacketCounter::default();
+        assert_eq!(counter.arrived(100, GAP), Arrival::Next(0));
+        assert_eq!(counter.arrived(201, GAP), Arrival::Next(100));
+        // A corrupt counter, and the stream going on where it was
+        assert_eq!(counter.arrived(20_000, GAP), Arrival::Resync);
+        assert_eq!(counter.arrived(202, GAP), Arrival::Resync);
+        assert_eq!(counter.arrived(203, GAP), Arrival::Next(0));
+        assert_eq!(counter.arrived(150, GAP), Arrival::Late);
+        assert_eq!(counter.arrived(100, GAP), Arrival::Resync);
+    }
+}
@@ -0,0 +1,992 @@
+//! An in-memory mitch that answers the command protocol and streams synthetic data.
+//!
+//! Lets the app run without insoles or a Bluetooth adapter, see `--simulate`.
+
+use std::{
+    f32::consts::PI,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::eyre;
+use futures::{FutureExt, StreamExt, channel::mpsc, future::BoxFuture};
+use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
+
+use super::{
+    BluetoothEvent,
+    adapter::{AdapterChoice, AdapterInfo, AdapterState},
+    mitch::{COMMAND_CHAR, DATA_CHAR, Mitch, MitchState},
+    protocol::{Command, FirmwareVersion, Frequency, Response, StreamConfig},
+    readout::crc32,
+    registry::Registry,
+    sample::{PRESSURE_CELLS, StreamMode},
+    transport::{ATT_HEADER_LEN, MitchTransport, Notification, Notifications},
+};
+use crate::event::Event;
+
+/// Error code the simulated firmware answers malformed commands with.
+const ERROR_INVALID_COMMAND: u8 = 0x01;
+/// Error code for commands referring to a log file that does not exist.
+const ERROR_INVALID_ARGUMENT: u8 = 0x02;
+
+/// Error code of a failed firmware image verification.
+const ERROR_CHECKSUM: u8 = 0x03;
+
+/// Hardware revision reported by simulated devices.
+pub const SIM_HARDWARE: u8 = 0x01;
+/// Time the simulated link takes to acknowledge a write.
+const WRITE_LATENCY: Duration = Duration::from_millis(2);
+/// Time a simulated device takes to reboot.
+const REBOOT_DELAY: Duration = Duration::from_millis(50);
+
+/// Size of the simulated log memory.
+const MEMORY_SIZE: u32 = 8 * 1024 * 1024;
+/// Battery drain of a simulated device, in percent per minute.
+const BATTERY_DRAIN: f64 = 1.0;
+/// Relative error of the simulated device clock, as of a typical crystal.
+const CLOCK_DRIFT: f64 = 30e-6;
+
+/// Longest log kept in memory, longer logs are truncated.
+const MAX_LOG: Duration = Duration::from_secs(600);
+/// Bytes of log data per readout notification.
+const READOUT_CHUNK: usize = 240;
+/// ATT MTU of the simulated link, a readout notification just fits.
+const SIM_MTU: usize = 247;
+
+/// One in this many data packets is lost on the simulated link.
+const PACKET_LOSS: u32 = 200;
+
+/// Rate at which packets are sent, higher frequencies put several frames into one packet.
+const PACKET_RATE: f64 = 50.0;
+
+/// A simulated mitch device, reached through one of the simulated adapters.
+pub struct SimulatedMitch {
+    address: String,
+    adapter: String,
+    inner: Arc<Mutex<SimState>>,
+    created: Instant,
+    exchange: tokio::sync::Mutex<()>,
+}
+
+#[derive(Default)]
+struct SimState {
+    connected: bool,
+    /// Adapter the device was last connected through.
+    link: Option<String>,
+    discovered: bool,
+    subscribed: bool,
+    state: Option<MitchState>,
+    /// Reply to the last command, returned by the next read of the command characteristic.
+    reply: Vec<u8>,
+    listeners: Vec<mpsc::UnboundedSender<Notification>>,
+    /// Incremented on every state change so a running data generator knows when to stop.
+    epoch: u64,
+    /// Configuration, start and device time of the start of the log being recorded.
+    log: Option<(StreamConfig, Instant, u64)>,
+    files: Vec<SimFile>,
+    firmware: FirmwareVersion,
+    /// Firmware image received by the bootloader.
+    boot: Option<SimBoot>,
+    serial: u64,
+    /// Battery charge when the simulation started.
+    charge: f64,
+    powered: Option<Instant>,
+    /// Device time in microseconds when the clock was last set, and when that was.
+    clock: Option<(u64, Instant)>,
+    /// Like early firmware, reject the time commands and do not report the first sample time.
+    clockless: bool,
+    /// Offsets of readout chunks the link loses, each once.
+    lost_chunks: Vec<u32>,
+    /// ATT MTU of the link, longer writes fail.
+    mtu: usize,
+}
+
+struct SimBoot {
+    crc: u32,
+    version: FirmwareVersion,
+    image: Vec<u8>,
+    verified: bool,
+}
+
+/// A log file in the memory of the simulated device.
+struct SimFile {
+    config: StreamConfig,
+    start: u64,
+    data: Vec<u8>,
+}
+
+impl SimFile {
+    /// The synthetic data a device logging with `config` for `duration` from the device time
+    /// `start` on would have recorded.
+    fn record(config: StreamConfig, duration: Duration, start: u64) -> Self {
+        let frames = (duration.min(MAX_LOG).as_secs_f64() * config.frequency.hz()) as u64;
+        Self {
+            config,
+            start,
+            data: (0..frames).flat_map(|n| frame(config.mode, n)).collect(),
+        }
+    }
+
+    fn info(&self) -> Vec<u8> {
+        let mut info = vec![self.config.mode as u8, self.config.frequency as u8];
+        info.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        info.extend_from_slice(&crc32(&self.data).to_le_bytes());
+        info.extend_from_slice(&self.start.to_le_bytes());
+        info
+    }
+}
+
+impl SimState {
+    fn set_state(&mut self, state: MitchState) {
+        if state != MitchState::SysLog
+            && let Some((config, started, start)) = self.log.take()
+        {
+            self.files
+                .push(SimFile::record(config, started.elapsed(), start));
+        }
+        self.state = Some(state);
+        self.epoch += 1;
+    }
+
+    fn battery(&self) -> u8 {
+        let minutes = self
+            .powered
+            .map_or(0.0, |p| p.elapsed().as_secs_f64() / 60.0);
+        (self.charge - minutes * BATTERY_DRAIN).max(0.0) as u8
+    }
+
+    /// Free bytes of the log memory, counting the log being recorded.
+    fn free_memory(&self) -> u32 {
+        let stored: usize = self.files.iter().map(|f| f.data.len()).sum();
+        let logging = self.log.map_or(0, |(config, started, _)| {
+            (started.elapsed().min(MAX_LOG).as_secs_f64()
+                * config.frequency.hz()
+                * config.mode.frame_len() as f64) as usize
+        });
+        MEMORY_SIZE.saturating_sub((stored + logging) as u32)
+    }
+
+    /// Device clock in microseconds since the Unix epoch.
+    fn time(&self) -> u64 {
+        self.clock.map_or(0, |(set, at)| {
+            set + (at.elapsed().as_secs_f64() * 1e6 * (1.0 + CLOCK_DRIFT)) as u64
+        })
+    }
+
+    fn notify(&mut self, notification: Notification) {
+        if self.subscribed {
+            self.listeners
+                .retain(|l| l.unbounded_send(notification.clone()).is_ok());
+        }
+    }
+}
+
+impl SimulatedMitch {
+    pub fn new(address: impl Into<String>) -> Self {
+        let address = address.into();
+        let serial = u64::from_str_radix(&address.replace(':', ""), 16).unwrap_or_default();
+        Self {
+            address,
+            adapter: SIM_ADAPTERS[0].to_string(),
+            inner: Arc::new(Mutex::new(SimState {
+                serial,
+                // Spread the charge so some devices show up as needing a charge
+                charge: 100.0 - (serial % 6) as f64 * 18.0,
+                powered: Some(Instant::now()),
+                // Like a device without a backup battery, the clock starts at zero
+                clock: Some((0, Instant::now())),
+                state: Some(MitchState::SysIdle),
+                firmware: FirmwareVersion {
+                    major: 1,
+                    minor: 0,
+                    patch: 0,
+                },
+                mtu: SIM_MTU,
+                ..Default::default()
+            })),
+            created: Instant::now(),
+            exchange: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// A device whose firmware has no clock.
+    #[cfg(test)]
+    pub fn without_clock(self) -> Self {
+        self.inner.lock().unwrap().clockless = true;
+        self
+    }
+
+    /// A device whose link negotiated `mtu`.
+    #[cfg(test)]
+    pub fn with_mtu(self, mtu: usize) -> Self {
+        self.inner.lock().unwrap().mtu = mtu;
+        self
+    }
+
+    /// The same device, reached through the simulated adapter called `adapter`.
+    pub fn via(&self, adapter: &str) -> Self {
+        Self {
+            address: self.address.clone(),
+            adapter: adapter.to_string(),
+            inner: self.inner.clone(),
+            created: self.created,
+            exchange: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// The adapter the device is connected through, if it is.
+    pub fn connected_via(&self) -> Option<String> {
+        let state = self.inner.lock().unwrap();
+        state.link.clone().filter(|_| state.connected)
+    }
+
+    /// Signal strength of the advertisements and the link, in dBm.
+    ///
+    /// Differs between devices and wobbles slowly like that of an insole being walked around.
+    pub fn rssi(&self) -> i16 {
+        let serial = self.inner.lock().unwrap().serial;
+        let wobble = (self.created.elapsed().as_secs_f64() * 0.7).sin() * 4.0;
+        -50 - (serial % 5) as i16 * 7 + wobble as i16
+    }
+
+    fn ensure_ready(state: &SimState) -> color_eyre::Result<()> {
+        if !state.connected || !state.discovered {
+            return Err(eyre!("Simulated mitch is not connected"));
+        }
+        Ok(())
+    }
+
+    /// Handles `data` written to `characteristic`.
+    fn receive(&self, characteristic: Uuid, data: &[u8]) -> color_eyre::Result<()> {
+        let mut state = self.inner.lock().unwrap();
+        Self::ensure_ready(&state)?;
+        if characteristic != COMMAND_CHAR {
+            return Err(eyre!("Characteristic {characteristic} not writable"));
+        }
+        if data.len() > state.mtu - ATT_HEADER_LEN {
+            return Err(eyre!(
+                "Write of {} bytes exceeds the MTU of {}",
+                data.len(),
+                state.mtu
+            ));
+        }
+        self.handle_command(&mut state, data);
+        Ok(())
+    }
+
+    fn handle_command(&self, state: &mut SimState, bytes: &[u8]) {
+        let id = bytes.first().copied().unwrap_or_default();
+        let payload = match Command::decode(bytes) {
+            Ok(command) => self.execute(state, command),
+            Err(_) => Err(ERROR_INVALID_COMMAND),
+        };
+        state.reply = match payload {
+            Ok(payload) => Response {
+                command: id,
+                payload,
+            }
+            .encode()
+            .unwrap_or_else(|_| Response::encode_error(id, ERROR_INVALID_ARGUMENT)),
+            Err(code) => Response::encode_error(id, code),
+        };
+    }
+
+    /// Runs `command`, returning the reply payload or an error code.
+    fn execute(&self, state: &mut SimState, command: Command) -> Result<Vec<u8>, u8> {
+        let in_bootloader = matches!(
+            state.state,
+            Some(MitchState::BootIdle | MitchState::BootDownload)
+        );
+        match command {
+            Command::GetState => {
+                return Ok(vec![state.state.unwrap_or(MitchState::SysIdle) as u8]);
+            }
+            Command::GetFirmwareVersion => {
+                let v = state.firmware;
+                return Ok(vec![SIM_HARDWARE, v.major, v.minor, v.patch]);
+            }
+            Command::Reboot => {
+                let image = state.boot.take().filter(|b| b.verified);
+                if let Some(image) = image {
+                    state.firmware = image.version;
+                }
+                self.spawn_reboot(MitchState::SysIdle);
+            }
+            // The bootloader only understands the firmware commands
+            Command::FirmwareBegin { size, crc, version } if in_bootloader => {
+                state.boot = Some(SimBoot {
+                    crc,
+                    version,
+                    image: vec![0; size as usize],
+                    verified: false,
+                });
+                state.set_state(MitchState::BootDownload);
+            }
+            Command::FirmwareChunk { offset, data } if in_bootloader => {
+                let boot = state.boot.as_mut().ok_or(ERROR_INVALID_COMMAND)?;
+                let end = offset as usize + data.len();
+                boot.image
+                    .get_mut(offset as usize..end)
+                    .ok_or(ERROR_INVALID_ARGUMENT)?
+                    .copy_from_slice(&data);
+            }
+            Command::FirmwareVerify if in_bootloader => {
+                let boot = state.boot.as_mut().ok_or(ERROR_INVALID_COMMAND)?;
+                boot.verified = crc32(&boot.image) == boot.crc;
+                if !boot.verified {
+                    return Err(ERROR_CHECKSUM);
+                }
+            }
+            _ if in_bootloader => return Err(ERROR_INVALID_COMMAND),
+            Command::FirmwareBegin { .. }
+            | Command::FirmwareChunk { .. }
+            | Command::FirmwareVerify => return Err(ERROR_INVALID_COMMAND),
+            Command::GetBatteryCharge => return Ok(vec![state.battery()]),
+            Command::GetDeviceId => {
+                return Ok(state.serial.to_le_bytes().to_vec());
+            }
+            Command::GetMemoryStatus => {
+                let mut reply = state.free_memory().to_le_bytes().to_vec();
+                reply.extend_from_slice(&MEMORY_SIZE.to_le_bytes());
+                return Ok(reply);
+            }
+            Command::EnterBootloader => {
+                state.set_state(MitchState::BootStartup);
+                self.spawn_reboot(MitchState::BootIdle);
+            }
+            Command::SetState(s) => state.set_state(s),
+            Command::SetTime(_) | Command::GetTime if state.clockless => {
+                return Err(ERROR_INVALID_COMMAND);
+            }
+            Command::SetTime(time) => state.clock = Some((time, Instant::now())),
+            Command::GetTime => return Ok(state.time().to_le_bytes().to_vec()),
+            Command::StartStream(config) => {
+                state.set_state(MitchState::SysTx);
+                self.spawn_stream(config, state.epoch);
+                if state.clockless {
+                    return Ok(Vec::new());
+                }
+                return Ok(state.time().to_le_bytes().to_vec());
+            }
+            Command::StartLog(config) => {
+                state.set_state(MitchState::SysLog);
+                state.log = Some((config, Instant::now(), state.time()));
+            }
+            Command::GetFileCount => return Ok(vec![state.files.len() as u8]),
+            Command::GetFileInfo(file) => {
+                let f = state
+                    .files
+                    .get(file as usize)
+                    .ok_or(ERROR_INVALID_ARGUMENT)?;
+                return Ok(f.info());
+            }
+            Command::ReadFile { file, offset } => {
+                let f = state
+                    .files
+                    .get(file as usize)
+                    .ok_or(ERROR_INVALID_ARGUMENT)?;
+                let data = f.data.get(offset as usize..).unwrap_or_default().to_vec();
+                state.set_state(MitchState::SysReadout);
+                self.spawn_readout(data, offset, state.epoch);
+            }
+            Command::EraseMemory => state.files.clear(),
+        }
+        Ok(Vec::new())
+    }
+
+    /// Drops the link shortly after the reply was read, as a rebooting device does, and comes
+    /// back up in `state`.
+    fn spawn_reboot(&self, state: MitchState) {
+        let inner = self.inner.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(REBOOT_DELAY).await;
+            let mut sim = inner.lock().unwrap();
+            sim.connected = false;
+            sim.discovered = false;
+            sim.subscribed = false;
+            sim.listeners.clear();
+            sim.set_state(state);
+        });
+    }
+
+    /// Emits data notifications for `config` until the state changes.
+    fn spawn_stream(&self, config: StreamConfig, epoch: u64) {
+        let inner = self.inner.clone();
+        let frames = frames_per_packet(config.frequency);
+        // Sampling runs off the same drifting crystal as the clock
+        let period =
+            Duration::from_secs_f64(frames as f64 / config.frequency.hz() / (1.0 + CLOCK_DRIFT));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            let mut counter: u16 = 0;
+            let mut n: u64 = 0;
+            loop {
+                interval.tick().await;
+                let mut state = inner.lock().unwrap();
+                if state.epoch != epoch {
+                    break;
+                }
+                let mut value = counter.to_le_bytes().to_vec();
+                for _ in 0..frames {
+                    value.extend(frame(config.mode, n));
+                    n += 1;
+                }
+                // Spread the losses pseudo randomly instead of every PACKET_LOSS packets
+                let lost = (counter as u32 + 1)
+                    .wrapping_mul(2_654_435_761)
+                    .is_multiple_of(PACKET_LOSS);
+                if !lost {
+                    state.notify(Notification {
+                        uuid: DATA_CHAR,
+                        value,
+                    });
+                }
+                counter = counter.wrapping_add(1);
+            }
+        });
+    }
+
+    /// Sends `data` as readout notifications starting at `offset`, then returns to idle.
+    fn spawn_readout(&self, data: Vec<u8>, offset: u32, epoch: u64) {
+        let inner = self.inner.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(2));
+            for (i, chunk) in data.chunks(READOUT_CHUNK).enumerate() {
+                interval.tick().await;
+                let mut state = inner.lock().unwrap();
+                if state.epoch != epoch {
+                    return;
+                }
+                let chunk_offset = offset + (i * READOUT_CHUNK) as u32;
+                if let Some(lost) = state.lost_chunks.iter().position(|&o| o == chunk_offset) {
+                    state.lost_chunks.remove(lost);
+                    continue;
+                }
+                let mut value = chunk_offset.to_le_bytes().to_vec();
+                value.extend_from_slice(chunk);
+                state.notify(Notification {
+                    uuid: DATA_CHAR,
+                    value,
+                });
+            }
+            let mut state = inner.lock().unwrap();
+            if state.epoch == epoch {
+                state.set_state(MitchState::SysIdle);
+            }
+        });
+    }
+}
+
+impl fmt::Debug for SimulatedMitch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SimulatedMitch")
+            .field(&self.address)
+            .field(&self.adapter)
+            .finish()
+    }
+}
+
+impl MitchTransport for SimulatedMitch {
+    fn address(&self) -> String {
+        self.address.clone()
+    }
+
+    fn id(&self) -> String {
+        self.address.clone()
+    }
+
+    fn adapter(&self) -> String {
+        self.adapter.clone()
+    }
+
+    fn connect(&self) -> BoxFuture<'_, color_eyre::Result<()>> {
+        async move {
+            let mut state = self.inner.lock().unwrap();
+            state.connected = true;
+            state.link = Some(self.adapter.clone());
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn mtu(&self) -> Option<usize> {
+        Some(self.inner.lock().unwrap().mtu)
+    }
+
+    fn answers_unconfirmed(&self) -> bool {
+        // The simulator is built on the same command ids
+        true
+    }
+
+    fn read_rssi(&self) -> BoxFuture<'_, color_eyre::Result<Option<i16>>> {
+        async move {
+            Self::ensure_ready(&self.inner.lock().unwrap())?;
+            Ok(Some(self.rssi()))
+        }
+        .boxed()
+    }
+
+    fn disconnect(&self) -> BoxFuture<'_, color_eyre::Result<()>> {
+        async move {
+            let mut state = self.inner.lock().unwrap();
+            state.connected = false;
+            state.discovered = false;
+            state.subscribed = false;
+            state.listeners.clear();
+            // Streaming and readout need the link, the firmware falls back to idle without it.
+            // Logging carries on.
+            if matches!(
+                state.state,
+                Some(MitchState::SysTx | MitchState::SysReadout)
+            ) {
+                state.set_state(MitchState::SysIdle);
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn discover(&self) -> BoxFuture<'_, color_eyre::Result<()>> {
+        async move {
+            let mut state = self.inner.lock().unwrap();
+            if !state.connected {
+                return Err(eyre!("Simulated mitch is not connected"));
+            }
+            state.discovered = true;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn read(&self, characteristic: Uuid) -> BoxFuture<'_, color_eyre::Result<Vec<u8>>> {
+        async move {
+            let state = self.inner.lock().unwrap();
+            Self::ensure_ready(&state)?;
+            if characteristic != COMMAND_CHAR {
+                return Err(eyre!("Characteristic {characteristic} not readable"));
+            }
+            Ok(state.reply.clone())
+        }
+        .boxed()
+    }
+
+    fn exchange_lock(&self) -> &tokio::sync::Mutex<()> {
+        &self.exchange
+    }
+
+    fn write<'a>(
+        &'a self,
+        characteristic: Uuid,
+        data: &'a [u8],
+    ) -> BoxFuture<'a, color_eyre::Result<()>> {
+        async move {
+            self.receive(characteristic, data)?;
+            tokio::time::sleep(WRITE_LATENCY).await;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn subscribe(&self, characteristic: Uuid) -> BoxFuture<'_, color_eyre::Result<()>> {
+        async move {
+            let mut state = self.inner.lock().unwrap();
+            Self::ensure_ready(&state)?;
+            if characteristic != DATA_CHAR {
+                return Err(eyre!("Characteristic {characteristic} does not notify"));
+            }
+            state.subscribed = true;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn notifications(&self) -> BoxFuture<'_, color_eyre::Result<Notifications>> {
+        async move {
+            let (tx, rx) = mpsc::unbounded();
+            self.inner.lock().unwrap().listeners.push(tx);
+            Ok(rx.boxed())
+        }
+        .boxed()
+    }
+}
+
+fn frames_per_packet(frequency: Frequency) -> usize {
+    (frequency.hz() / PACKET_RATE).max(1.0) as usize
+}
+
+/// Raw bytes of the `n`-th synthetic frame for `mode`, laid out as described in
+/// [`sample`](super::sample).
+fn frame(mode: StreamMode, n: u64) -> Vec<u8> {
+    let t = n as f32 * 0.05;
+    let mut frame = Vec::with_capacity(mode.frame_len());
+    if matches!(mode, StreamMode::Pressure | StreamMode::PressureImu) {
+        frame.extend(
+            (0..PRESSURE_CELLS).map(|i| (127.0 + 120.0 * (t + i as f32 * 0.4).sin()) as u8),
+        );
+    }
+    let axes = match mode {
+        StreamMode::Imu6 | StreamMode::PressureImu => 6,
+        StreamMode::Imu9 => 9,
+        StreamMode::Pressure | StreamMode::Orientation => 0,
+    };
+    for axis in 0..axes {
+        let v = (8000.0 * (t + axis as f32 * PI / 3.0).sin()) as i16;
+        frame.extend(v.to_le_bytes());
+    }
+    if mode == StreamMode::Orientation {
+        // Rotation around the vertical axis
+        let (sin, cos) = (t / 2.0).sin_cos();
+        for q in [cos, 0.0, 0.0, sin] {
+            frame.extend(((q * 16384.0) as i16).to_le_bytes());
+        }
+    }
+    frame
+}
+
+/// Transmit power simulated mitches advertise, in dBm.
+const SIM_TX_POWER: i16 = 0;
+
+/// Interval at which simulated mitches advertise while not connected.
+const ADVERTISING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Names of the adapters the simulated mitches are reached with, every mitch is in range of all.
+pub const SIM_ADAPTERS: [&str; 2] = ["sim0", "sim1"];
+
+/// Announces a fixed number of simulated mitches instead of scanning for real ones.
+///
+/// Like the adapters during a scan, it then keeps reporting their advertisements and the links
+/// coming up and going down.
+pub struct SimDiscoverTask {
+    sender: UnboundedSender<Event>,
+    count: usize,
+    adapter: AdapterChoice,
+    registry: Registry,
+}
+
+impl SimDiscoverTask {
+    pub fn new(
+        sender: UnboundedSender<Event>,
+        count: usize,
+        adapter: AdapterChoice,
+        registry: Registry,
+    ) -> Self {
+        Self {
+            sender,
+            count,
+            adapter,
+            registry,
+        }
+    }
+
+    pub async fn run(self) -> color_eyre::Result<()> {
+        let adapters: Vec<AdapterInfo> = SIM_ADAPTERS
+            .iter()
+            .enumerate()
+            .map(|(i, name)| AdapterInfo {
+                name: name.to_string(),
+                state: if self.adapter.picks(i, name) {
+                    AdapterState::Scanning
+                } else {
+                    AdapterState::Unused
+                },
+            })
+            .collect();
+        let scanning: Vec<&str> = adapters
+            .iter()
+            .filter(|a| a.state == AdapterState::Scanning)
+            .map(|a| a.name.as_str())
+            .collect();
+        self.send(BluetoothEvent::Adapters(adapters.clone()));
+        let Some((first, others)) = scanning.split_first() else {
+            return Ok(());
+        };
+        let mut devices = Vec::new();
+        for i in 1..=self.count {
+            let transport = SimulatedMitch::new(format!("00:00:00:00:00:{i:02X}")).via(first);
+            let mut mitch =
+                Mitch::new(format!("mitch-sim-{i}"), Arc::new(transport.via(first))).await?;
+            if let Some(entry) = self.registry.get(&transport.id()) {
+                mitch.apply(entry);
+            }
+            self.send(BluetoothEvent::Discovered(Box::new(mitch)));
+            for other in others {
+                self.send(BluetoothEvent::Reachable(Arc::new(transport.via(other))));
+            }
+            devices.push((transport, None));
+        }
+
+        let mut interval = tokio::time::interval(ADVERTISING_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = self.sender.closed() => break,
+                _ = interval.tick() => {}
+            }
+            for (transport, link) in &mut devices {
+                let current = transport.connected_via();
+                if current != *link {
+                    if let Some(adapter) = link.take() {
+                        self.send(BluetoothEvent::Disconnected {
+                            id: transport.id(),
+                            adapter,
+                        });
+                    }
+                    if let Some(adapter) = current.clone() {
+                        self.send(BluetoothEvent::Connected {
+                            id: transport.id(),
+                            adapter,
+                        });
+                    }
+                    *link = current;
+                }
+                // A connected device stops advertising
+                if link.is_none() {
+                    self.send(BluetoothEvent::Updated {
+                        id: transport.id(),
+                        name: None,
+                        rssi: Some(transport.rssi()),
+                        tx_power: Some(SIM_TX_POWER),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&self, event: BluetoothEvent) {
+        // Ignores the result because shutting down the app drops the receiver
+        let _ = self.sender.send(Event::Bluetooth(event));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::{
+        actor::MitchCommand,
+        firmware::{FirmwareImage, FirmwareStatus},
+        mitch::send_command,
+        readout::{LogFile, ReadoutSession, ReadoutStatus},
+    };
+
+    const CONFIG: StreamConfig = StreamConfig {
+        mode: StreamMode::Imu6,
+        frequency: Frequency::Hz100,
+    };
+
+    async fn connected(transport: &Arc<SimulatedMitch>) -> Mitch {
+        let mut mitch = Mitch::new("mitch-test".to_string(), transport.clone())
+            .await
+            .unwrap();
+        mitch.connect().await.unwrap();
+        mitch
+    }
+
+    async fn wait_for(done: impl Fn() -> bool) {
+        for _ in 0..100 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("Timed out");
+    }
+
+    fn streamed(mitch: &Mitch) -> bool {
+        mitch.stream_stats().is_some_and(|s| s.samples > 0)
+    }
+
+    /// Whether the log being recorded holds more than a few readout chunks.
+    fn logged(transport: &SimulatedMitch) -> bool {
+        let free = transport.inner.lock().unwrap().free_memory();
+        MEMORY_SIZE - free > 3 * READOUT_CHUNK as u32
+    }
+
+    #[tokio::test]
+    async fn records_and_disconnects() {
+        let transport = Arc::new(SimulatedMitch::new("00:00:00:00:00:01"));
+        let mut mitch = connected(&transport).await;
+        assert_eq!(mitch.state(), Some(MitchState::SysIdle));
+        assert_eq!(transport.connected_via().as_deref(), Some(SIM_ADAPTERS[0]));
+
+        mitch.start_recording(CONFIG).await.unwrap();
+        wait_for(|| streamed(&mitch)).await;
+        mitch.update_state().await.unwrap();
+        assert_eq!(mitch.state(), Some(MitchState::SysTx));
+        let stats = mitch.stream_stats().unwrap();
+        assert_eq!(stats.malformed, 0);
+
+        mitch.stop_recording().await.unwrap();
+        mitch.update_state().await.unwrap();
+        assert_eq!(mitch.state(), Some(MitchState::SysIdle));
+        assert!(mitch.stream_stats().is_none());
+
+        mitch.disconnect().await.unwrap();
+        assert_eq!(transport.connected_via(), None);
+    }
+
+    #[tokio::test]
+    async fn records_without_clock() {
+        let transport = Arc::new(SimulatedMitch::new("00:00:00:00:00:04").without_clock());
+        let mut mitch = connected(&transport).await;
+        mitch.sync_clock().await.unwrap();
+        mitch.start_recording(CONFIG).await.unwrap();
+        wait_for(|| streamed(&mitch)).await;
+        mitch.stop_recording().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stop_recording_keeps_logging() {
+        let transport = Arc::new(SimulatedMitch::new("00:00:00:00:00:08"));
+        let mut mitch = connected(&transport).await;
+        mitch.start_logging(CONFIG).await.unwrap();
+        mitch.execute(MitchCommand::StopRecording).await.unwrap();
+        mitch.update_state().await.unwrap();
+        assert_eq!(mitch.state(), Some(MitchState::SysLog));
+        mitch.stop_logging().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_recording_on_invalid_reply() {
+        let transport = Arc::new(SimulatedMitch::new("00:00:00:00:00:05"));
+        let mut mitch = connected(&transport).await;
+        mitch.start_recording(CONFIG).await.unwrap();
+        transport.inner.lock().unwrap().charge = 150.0;
+        mitch.refresh_info().await.unwrap();
+        assert_eq!(mitch.take_notes().len(), 1);
+        // The rejected query is not sent again
+        mitch.refresh_info().await.unwrap();
+        assert!(mitch.take_notes().is_empty());
+        mitch.poll().await.unwrap();
+        assert!(mitch.stream_stats().is_some());
+        assert!(transport.connected_via().is_some());
+        mitch.stop_recording().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn connects_despite_invalid_reply() {
+        let transport = Arc::new(SimulatedMitch::new("00:00:00:00:00:0b"));
+        transport.inner.lock().unwrap().charge = 150.0;
+        let mut mitch = connected(&transport).await;
+        assert_eq!(mitch.state(), Some(MitchState::SysIdle));
+        assert!(transport.connected_via().is_some());
+        assert_eq!(mitch.take_notes().len(), 1);
+        mitch.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_out_despite_lost_chunks() {
+        let transport = Arc::new(SimulatedMitch::new("00:00:00:00:00:06"));
+        let mut mitch = connected(&transport).await;
+        mitch.start_logging(CONFIG).await.unwrap();
+        wait_for(|| logged(&transport)).await;
+        mitch.stop_logging().await.unwrap();
+        let info = send_command(transport.as_ref(), Command::GetFileInfo(0))
+            .await
+            .unwrap();
+        let file = LogFile::parse(0, &info).unwrap();
+        // The first chunk is lost twice, once when requested again as well
+        let chunk = READOUT_CHUNK as u32;
+        transport.inner.lock().unwrap().lost_chunks = vec![0, 0, chunk];
+
+        let dir = std::env::temp_dir().join(format!("mitchrs-readout-{}", std::process::id()));
+        let readout = ReadoutSession::start(transport.clone(), "mitch-test", file, None, &dir);
+        wait_for(|| readout.is_finished()).await;
+        let status = readout.status();
+        assert!(matches!(status, ReadoutStatus::Verified(_)), "{status:?}");
+        assert!(transport.inner.lock().unwrap().lost_chunks.is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reads_out_while_polling() {
+        let transport = Arc::new(SimulatedMitch::new("00:00:00:00:00:07"));
+        let mut mitch = connected(&transport).await;
+        mitch.start_logging(CONFIG).await.unwrap();
+        wait_for(|| logged(&transport)).await;
+        mitch.stop_logging().await.unwrap();
+        let info = send_command(transport.as_ref(), Command::GetFileInfo(0))
+            .await
+            .unwrap();
+        let file = LogFile::parse(0, &info).unwrap();
+        // Lost chunks are requested again, so the readout writes commands throughout
+        let chunk = READOUT_CHUNK as u32;
+        transport.inner.lock().unwrap().lost_chunks = vec![0, chunk, 2 * chunk];
+
+        let dir = std::env::temp_dir().join(format!("mitchrs-polled-{}", std::process::id()));
+        let readout = ReadoutSession::start(transport.clone(), "mitch-test", file, None, &dir);
+        while !readout.is_finished() {
+            mitch.poll().await.unwrap();
+        }
+        let status = readout.status();
+        assert!(matches!(status, ReadoutStatus::Verified(_)), "{status:?}");
+        assert!(mitch.snapshot(None).connected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumes_recording_after_link_loss() {
+        let transport = Arc::new(SimulatedMitch::new("00:00:00:00:00:02"));
+        let mut mitch = connected(&transport).await;
+        mitch.start_recording(CONFIG).await.unwrap();
+
+        // The link drops while the device reboots
+        send_command(transport.as_ref(), Command::Reboot)
+            .await
+            .unwrap();
+        wait_for(|| transport.connected_via().is_none()).await;
+        let error = mitch.poll().await.unwrap_err();
+        assert!(error.to_string().starts_with("Link lost"), "{error}");
+        assert!(mitch.stream_stats().is_none());
+
+        // Reconnecting waits for the first delay of the policy
+        for _ in 0..100 {
+            mitch.poll().await.unwrap();
+            if mitch.state() == Some(MitchState::SysTx) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(mitch.state(), Some(MitchState::SysTx));
+        wait_for(|| streamed(&mitch)).await;
+        mitch.disconnect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_failed_update() {
+        let transport = Arc::new(SimulatedMitch::new("00:00:00:00:00:09"));
+        let mut mitch = connected(&transport).await;
+        let data = vec![0; 100];
+        // Built for other hardware, the update fails right away
+        let image = FirmwareImage {
+            hardware: SIM_HARDWARE + 1,
+            version: FirmwareVersion {
+                major: 1,
+                minor: 1,
+                patch: 0,
+            },
+            crc: crc32(&data),
+            data,
+        };
+        mitch.start_firmware_update(Arc::new(image), false).unwrap();
+        wait_for(|| !mitch.is_updating_firmware()).await;
+        mitch.poll().await.unwrap();
+        assert!(!mitch.snapshot(None).connected);
+        assert_eq!(transport.connected_via(), None);
+
+        mitch.connect().await.unwrap();
+        for _ in 0..3 {
+            mitch.poll().await.unwrap();
+        }
+        let snapshot = mitch.snapshot(None);
+        assert!(snapshot.connected);
+        assert!(
+            matches!(snapshot.firmware, Some(FirmwareStatus::Failed(_))),
+            "{:?}",
+            snapshot.firmware
+        );
+        assert!(transport.connected_via().is_some());
+        mitch.disconnect().await.unwrap();
+    }
+}
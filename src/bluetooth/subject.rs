@@ -0,0 +1,268 @@
+//! A left and a right insole worn by the same person.
+//!
+//! The two mitches of a [`Subject`] connect, start and stop together. Optionally they also feed
+//! a [`PairStream`], one LSL stream with the channels of both sides, so gait analyses do not
+//! need to merge the streams afterwards.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::eyre::eyre;
+use lsl::Pushable;
+
+use super::{
+    outlet::{self, SendOutlet},
+    protocol::StreamConfig,
+};
+use crate::xdf::{XdfRecorder, XdfStream};
+
+/// Samples a side may run ahead of the other before the missing ones are given up as lost.
+const MAX_AHEAD: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl Side {
+    /// `L` or `R`, the prefix of the channel labels in the combined stream.
+    pub fn letter(&self) -> &'static str {
+        match self {
+            Side::Left => "L",
+            Side::Right => "R",
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Side::Left => 0,
+            Side::Right => 1,
+        }
+    }
+}
+
+/// A pair of mitches, given by their ids.
+#[derive(Clone, Debug)]
+pub struct Subject {
+    pub name: String,
+    pub left: String,
+    pub right: String,
+    /// The combined stream, if the pair publishes one.
+    pub combined: Option<Arc<PairStream>>,
+}
+
+impl Subject {
+    /// The side of the mitch with `id`, if it belongs to the subject.
+    pub fn side(&self, id: &str) -> Option<Side> {
+        if self.left == id {
+            Some(Side::Left)
+        } else if self.right == id {
+            Some(Side::Right)
+        } else {
+            None
+        }
+    }
+
+    /// Id of the other mitch of the pair.
+    pub fn partner(&self, id: &str) -> Option<&str> {
+        match self.side(id)? {
+            Side::Left => Some(&self.right),
+            Side::Right => Some(&self.left),
+        }
+    }
+}
+
+/// The combined stream of a subject.
+///
+/// The outlet is opened by the first side that starts recording and closed once both stopped.
+/// The samples of both sides are paired by their timestamps: a left and a right sample less than
+/// half a sample period apart are pushed together at their mean time. A sample without a partner
+/// that close, because the other side lost it or was stamped apart from it, is pushed with NaN
+/// for the other side. Samples not after the last one pushed are dropped, like those of a side
+/// that starts again while the other one records, after its link was restored.
+pub struct PairStream {
+    name: String,
+    xdf: XdfRecorder,
+    inner: Mutex<PairState>,
+}
+
+#[derive(Default)]
+struct PairState {
+    outlet: Option<(StreamConfig, SendOutlet, XdfStream)>,
+    /// Sides currently recording.
+    attached: [bool; 2],
+    /// Stamped samples in the order of their times, per side, waiting for the other side.
+    pending: [VecDeque<(f64, Vec<f64>)>; 2],
+    /// LSL time of the last sample pushed.
+    last: Option<f64>,
+}
+
+impl PairStream {
+    /// The combined stream of the subject `name`, also recorded by `xdf` while it records.
+    pub fn new(name: String, xdf: XdfRecorder) -> Self {
+        Self {
+            name,
+            xdf,
+            inner: Mutex::new(PairState::default()),
+        }
+    }
+
+    /// Takes `side` recording with `config` into the stream, opening the outlet if needed.
+    ///
+    /// Fails if the other side records with another configuration.
+    pub fn attach(&self, side: Side, config: StreamConfig) -> color_eyre::Result<()> {
+        let mut state = self.inner.lock().unwrap();
+        match &state.outlet {
+            Some((running, ..)) if *running != config => {
+                return Err(eyre!(
+                    "{} records with {running:?} on the other side, the combined stream needs \
+                     the same configuration on both",
+                    self.name
+                ));
+            }
+            Some(_) => {}
+            None => {
+                let info = outlet::pair_stream_info(&self.name, config)?;
+                let outlet = SendOutlet::new(&info)?;
+                state.outlet = Some((config, outlet, self.xdf.stream(info.to_xml()?)));
+                state.last = None;
+            }
+        }
+        state.attached[side.index()] = true;
+        state.pending[side.index()].clear();
+        Ok(())
+    }
+
+    /// Ends the recording of `side`, the outlet is closed once neither side records.
+    pub fn detach(&self, side: Side) {
+        let mut state = self.inner.lock().unwrap();
+        state.attached[side.index()] = false;
+        state.pending[side.index()].clear();
+        if state.attached == [false; 2] {
+            state.outlet = None;
+        }
+    }
+
+    /// Adds samples of `side` given as LSL time and values, and pushes every sample whose
+    /// partner on the other side is known or will not come.
+    pub fn push(&self, side: Side, samples: &[(f64, Vec<f64>)]) {
+        let mut state = self.inner.lock().unwrap();
+        let Some((config, ..)) = &state.outlet else {
+            return;
+        };
+        let tolerance = 0.5 / config.frequency.hz();
+        let channels = config.mode.channel_count();
+        state.pending[side.index()].extend(samples.iter().cloned());
+        let (chunk, stamps) = state.pair(tolerance, channels);
+        if let Some((_, outlet, xdf)) = &state.outlet
+            && !chunk.is_empty()
+        {
+            // Pushing only fails for a wrong channel count, which is derived from the config
+            let _ = outlet.push_chunk_stamped(&chunk, &stamps);
+            xdf.push(&chunk, &stamps);
+        }
+    }
+}
+
+impl PairState {
+    /// Takes the pending samples that can be pushed, each with the sample of the other side
+    /// within `tolerance` of it or NaN for the `channels` of that side, and their times.
+    fn pair(&mut self, tolerance: f64, channels: usize) -> (Vec<Vec<f64>>, Vec<f64>) {
+        let mut chunk = Vec::new();
+        let mut stamps = Vec::new();
+        loop {
+            let fronts = [0, 1].map(|s| self.pending[s].front().map(|(time, _)| *time));
+            let take = match fronts {
+                [Some(left), Some(right)] if (left - right).abs() <= tolerance => [true; 2],
+                // The times only increase, nothing that comes later is closer to the earlier one
+                [Some(left), Some(right)] => [left < right, right < left],
+                // The side without samples may still deliver a partner, unless it is too far
+                // behind or not recording
+                [Some(_), None] | [None, Some(_)]
+                    if self.pending[0].len() + self.pending[1].len() > MAX_AHEAD
+                        || self.attached != [true; 2] =>
+                {
+                    fronts.map(|f| f.is_some())
+                }
+                _ => break,
+            };
+            let taken = [0, 1].map(|s| {
+                if take[s] {
+                    self.pending[s].pop_front()
+                } else {
+                    None
+                }
+            });
+            let times: Vec<f64> = taken.iter().flatten().map(|(time, _)| *time).collect();
+            let time = times.iter().sum::<f64>() / times.len() as f64;
+            if self.last.is_some_and(|last| time <= last) {
+                continue;
+            }
+            let mut sample = Vec::with_capacity(2 * channels);
+            for side in taken {
+                match side {
+                    Some((_, values)) => sample.extend(values),
+                    None => sample.extend(std::iter::repeat_n(f64::NAN, channels)),
+                }
+            }
+            chunk.push(sample);
+            stamps.push(time);
+            self.last = Some(time);
+        }
+        (chunk, stamps)
+    }
+}
+
+impl fmt::Debug for PairStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PairStream").field(&self.name).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Half a period at 100 Hz.
+    const TOLERANCE: f64 = 0.005;
+
+    fn recording() -> PairState {
+        PairState {
+            attached: [true; 2],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pairs_samples_close_in_time() {
+        let mut state = recording();
+        // The right side is stamped 3 ms later and lost its second sample
+        state.pending[0].extend([(1.00, vec![1.0]), (1.01, vec![2.0]), (1.02, vec![3.0])]);
+        state.pending[1].extend([(1.003, vec![-1.0]), (1.023, vec![-3.0])]);
+        let (chunk, stamps) = state.pair(TOLERANCE, 1);
+        assert_eq!(chunk[0], [1.0, -1.0]);
+        assert!(chunk[1][0] == 2.0 && chunk[1][1].is_nan(), "{chunk:?}");
+        assert_eq!(chunk[2], [3.0, -3.0]);
+        assert_eq!(stamps, [1.0015, 1.01, 1.0215]);
+    }
+
+    #[test]
+    fn waits_for_the_other_side_and_drops_late_samples() {
+        let mut state = recording();
+        state.pending[0].extend([(1.00, vec![1.0]), (1.01, vec![2.0])]);
+        assert!(state.pair(TOLERANCE, 1).0.is_empty());
+
+        state.pending[1].push_back((1.001, vec![-1.0]));
+        assert_eq!(state.pair(TOLERANCE, 1).0, [[1.0, -1.0]]);
+
+        // A side that rejoins with samples from before the last one pushed
+        state.pending[1].extend([(0.95, vec![-9.0]), (1.011, vec![-2.0])]);
+        let (chunk, stamps) = state.pair(TOLERANCE, 1);
+        assert_eq!(chunk, [[2.0, -2.0]]);
+        assert_eq!(stamps, [1.0105]);
+    }
+}
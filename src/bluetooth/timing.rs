@@ -0,0 +1,101 @@
+//! Online estimate of the true sample period of a stream from the arrival times of its packets.
+//!
+//! BLE delivers notifications in bursts at connection events, so single arrival times are off by
+//! up to a connection interval. The sample index on the other hand is exact. Fitting a line
+//! through `(index, arrival)` with exponential forgetting recovers the sample period of the
+//! device as seen by the host clock, which differs from the nominal one by the drift of the
+//! device crystal, and the spread around that line measures the jitter.
+
+/// Number of packets the estimate effectively averages over.
+const WINDOW: f64 = 3000.0;
+/// Span of samples in seconds below which the fitted period is too uncertain to use.
+const MIN_SPAN: f64 = 10.0;
+/// Largest plausible relative deviation from the nominal rate, beyond it the fit is ignored.
+const MAX_DRIFT: f64 = 1e-3;
+
+/// Fit of packet arrival times against sample indices.
+#[derive(Clone, Debug)]
+pub struct TimingEstimator {
+    rate: f64,
+    weight: f64,
+    /// Index and arrival of the first packet, the fit runs relative to them for precision.
+    origin: Option<(u64, f64)>,
+    mean_index: f64,
+    mean_arrival: f64,
+    var_index: f64,
+    covariance: f64,
+    residual: f64,
+    span: f64,
+}
+
+impl TimingEstimator {
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            weight: 0.0,
+            origin: None,
+            mean_index: 0.0,
+            mean_arrival: 0.0,
+            var_index: 0.0,
+            covariance: 0.0,
+            residual: 0.0,
+            span: 0.0,
+        }
+    }
+
+    /// Adds a packet whose last sample has `index` and that arrived at the LSL time `arrival`.
+    pub fn observe(&mut self, index: u64, arrival: f64) {
+        let (first_index, first_arrival) = *self.origin.get_or_insert((index, arrival));
+        let x = index as f64 - first_index as f64;
+        let y = arrival - first_arrival;
+        self.span = self.span.max(x / self.rate);
+
+        if self.weight > 0.0 {
+            let error = y - self.fitted(x);
+            self.residual += (error * error - self.residual) / self.weight;
+        }
+        self.weight = (self.weight + 1.0).min(WINDOW);
+        let alpha = 1.0 / self.weight;
+        let dx = x - self.mean_index;
+        let dy = y - self.mean_arrival;
+        self.mean_index += alpha * dx;
+        self.mean_arrival += alpha * dy;
+        self.var_index = (1.0 - alpha) * (self.var_index + alpha * dx * dx);
+        self.covariance = (1.0 - alpha) * (self.covariance + alpha * dx * dy);
+    }
+
+    /// Sample period in host seconds, the nominal one until enough packets arrived.
+    pub fn period(&self) -> f64 {
+        let nominal = 1.0 / self.rate;
+        if self.span < MIN_SPAN || self.var_index <= 0.0 {
+            return nominal;
+        }
+        let period = self.covariance / self.var_index;
+        if ((period - nominal) / nominal).abs() > MAX_DRIFT {
+            return nominal;
+        }
+        period
+    }
+
+    /// Drift of the device against the host clock in parts per million, positive if the device
+    /// samples slower than nominal.
+    pub fn drift_ppm(&self) -> f64 {
+        (self.period() * self.rate - 1.0) * 1e6
+    }
+
+    /// Standard deviation of the arrival times around the fit, in seconds.
+    pub fn jitter(&self) -> f64 {
+        self.residual.sqrt()
+    }
+
+    /// Host time of the sample with `index` from the fitted arrival times, which include the
+    /// mean transmission latency. `None` before the first packet.
+    pub fn host_time(&self, index: u64) -> Option<f64> {
+        let (first_index, first_arrival) = self.origin?;
+        Some(first_arrival + self.fitted(index as f64 - first_index as f64))
+    }
+
+    fn fitted(&self, x: f64) -> f64 {
+        self.mean_arrival + (x - self.mean_index) * self.period()
+    }
+}
@@ -0,0 +1,191 @@
+//! The link between a [`Mitch`](super::mitch::Mitch) and the device it talks to.
+
+use std::fmt;
+
+use btleplug::{
+    api::{BDAddr, Characteristic, Peripheral as _, WriteType},
+    platform::Peripheral,
+};
+use color_eyre::eyre::eyre;
+use futures::{FutureExt, StreamExt, future::BoxFuture, stream::BoxStream};
+use uuid::Uuid;
+
+/// A value notification sent by the device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notification {
+    /// The characteristic the notification belongs to.
+    pub uuid: Uuid,
+    pub value: Vec<u8>,
+}
+
+/// Stream of all notifications of the subscribed characteristics.
+pub type Notifications = BoxStream<'static, Notification>;
+
+/// The ATT MTU every BLE link supports, assumed where the negotiated one is not known.
+pub const DEFAULT_MTU: usize = 23;
+/// Bytes of an ATT packet taken by opcode and handle, the rest of the MTU carries the value.
+pub const ATT_HEADER_LEN: usize = 3;
+
+/// Operations a mitch needs from its link.
+///
+/// Implemented for BLE peripherals by [`BtleTransport`] and in memory by
+/// [`SimulatedMitch`](super::sim::SimulatedMitch).
+pub trait MitchTransport: Send + Sync + fmt::Debug {
+    /// Address of the device, used as LSL source id.
+    fn address(&self) -> String;
+
+    /// Identifier of the device, the same every time and on every adapter it is discovered.
+    fn id(&self) -> String;
+
+    /// Name of the adapter the device is reached through.
+    fn adapter(&self) -> String;
+
+    fn connect(&self) -> BoxFuture<'_, color_eyre::Result<()>>;
+
+    /// ATT MTU negotiated for the connection, `None` if the platform does not report it.
+    fn mtu(&self) -> Option<usize>;
+
+    /// Whether the device is known to understand the unconfirmed commands of the
+    /// [protocol](super::protocol). Those that change the device are only sent if it does.
+    fn answers_unconfirmed(&self) -> bool {
+        false
+    }
+
+    /// Signal strength of the connected device in dBm, `None` if the adapter does not report it.
+    fn read_rssi(&self) -> BoxFuture<'_, color_eyre::Result<Option<i16>>>;
+
+    fn disconnect(&self) -> BoxFuture<'_, color_eyre::Result<()>>;
+
+    /// Discovers the services and characteristics of a connected device.
+    fn discover(&self) -> BoxFuture<'_, color_eyre::Result<()>>;
+
+    fn read(&self, characteristic: Uuid) -> BoxFuture<'_, color_eyre::Result<Vec<u8>>>;
+
+    /// Held from writing a command until its reply is read, so concurrent exchanges do not
+    /// read each other's replies.
+    fn exchange_lock(&self) -> &tokio::sync::Mutex<()>;
+
+    /// Writes `data` to `characteristic` and waits for the device to acknowledge it.
+    fn write<'a>(
+        &'a self,
+        characteristic: Uuid,
+        data: &'a [u8],
+    ) -> BoxFuture<'a, color_eyre::Result<()>>;
+
+    fn subscribe(&self, characteristic: Uuid) -> BoxFuture<'_, color_eyre::Result<()>>;
+
+    /// Stream of notifications of all subscribed characteristics.
+    fn notifications(&self) -> BoxFuture<'_, color_eyre::Result<Notifications>>;
+}
+
+/// A mitch reached over Bluetooth LE, through the named adapter.
+pub struct BtleTransport(Peripheral, String, tokio::sync::Mutex<()>);
+
+impl BtleTransport {
+    pub fn new(per: Peripheral, adapter: String) -> Self {
+        Self(per, adapter, tokio::sync::Mutex::new(()))
+    }
+
+    fn characteristic(&self, uuid: Uuid) -> color_eyre::Result<Characteristic> {
+        self.0
+            .characteristics()
+            .into_iter()
+            .find(|c| c.uuid == uuid)
+            .ok_or_else(|| eyre!("Characteristic {uuid} not found"))
+    }
+}
+
+impl fmt::Debug for BtleTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BtleTransport")
+            .field(&self.0.address())
+            .field(&self.1)
+            .finish()
+    }
+}
+
+impl MitchTransport for BtleTransport {
+    fn address(&self) -> String {
+        self.0.address().to_string()
+    }
+
+    fn id(&self) -> String {
+        // The peripheral id differs between adapters, but CoreBluetooth hides the address
+        let address = self.0.address();
+        if address == BDAddr::default() {
+            self.0.id().to_string()
+        } else {
+            address.to_string()
+        }
+    }
+
+    fn adapter(&self) -> String {
+        self.1.clone()
+    }
+
+    fn connect(&self) -> BoxFuture<'_, color_eyre::Result<()>> {
+        async move { Ok(self.0.connect().await?) }.boxed()
+    }
+
+    fn mtu(&self) -> Option<usize> {
+        // btleplug does not expose the MTU it negotiated
+        None
+    }
+
+    fn read_rssi(&self) -> BoxFuture<'_, color_eyre::Result<Option<i16>>> {
+        async move { Ok(self.0.properties().await?.and_then(|p| p.rssi)) }.boxed()
+    }
+
+    fn disconnect(&self) -> BoxFuture<'_, color_eyre::Result<()>> {
+        async move { Ok(self.0.disconnect().await?) }.boxed()
+    }
+
+    fn discover(&self) -> BoxFuture<'_, color_eyre::Result<()>> {
+        async move { Ok(self.0.discover_services().await?) }.boxed()
+    }
+
+    fn read(&self, characteristic: Uuid) -> BoxFuture<'_, color_eyre::Result<Vec<u8>>> {
+        async move {
+            let c = self.characteristic(characteristic)?;
+            Ok(self.0.read(&c).await?)
+        }
+        .boxed()
+    }
+
+    fn exchange_lock(&self) -> &tokio::sync::Mutex<()> {
+        &self.2
+    }
+
+    fn write<'a>(
+        &'a self,
+        characteristic: Uuid,
+        data: &'a [u8],
+    ) -> BoxFuture<'a, color_eyre::Result<()>> {
+        async move {
+            let c = self.characteristic(characteristic)?;
+            Ok(self.0.write(&c, data, WriteType::WithResponse).await?)
+        }
+        .boxed()
+    }
+
+    fn subscribe(&self, characteristic: Uuid) -> BoxFuture<'_, color_eyre::Result<()>> {
+        async move {
+            let c = self.characteristic(characteristic)?;
+            Ok(self.0.subscribe(&c).await?)
+        }
+        .boxed()
+    }
+
+    fn notifications(&self) -> BoxFuture<'_, color_eyre::Result<Notifications>> {
+        async move {
+            let notifications = self.0.notifications().await?;
+            Ok(notifications
+                .map(|n| Notification {
+                    uuid: n.uuid,
+                    value: n.value,
+                })
+                .boxed())
+        }
+        .boxed()
+    }
+}
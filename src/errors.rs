@@ -0,0 +1,182 @@
+//! Errors that do not end the application, shown as toasts and kept in a scrollable log.
+
+use std::{
+    cmp::min,
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    buffer::Buffer,
+    layout::{Alignment, Rect},
+    style::{Color, Style, Stylize},
+    widgets::{Block, BorderType, Borders, Clear, Paragraph, Widget, WidgetRef, Wrap},
+};
+
+/// How long a new error is shown as toast.
+const TOAST_TIME: Duration = Duration::from_secs(5);
+/// Most toasts shown at once, the newest ones win.
+const MAX_TOASTS: usize = 3;
+/// Number of errors the log keeps before dropping the oldest.
+const CAPACITY: usize = 500;
+
+/// An error together with where and when it happened.
+#[derive(Clone, Debug)]
+pub struct LoggedError {
+    pub time: Instant,
+    /// Name of the mitch the error belongs to, `None` for errors of the app itself.
+    pub device: Option<String>,
+    pub message: String,
+    /// Only kept in the log, never shown as toast.
+    pub quiet: bool,
+}
+
+impl LoggedError {
+    fn source(&self) -> &str {
+        self.device.as_deref().unwrap_or("mitchrs")
+    }
+}
+
+/// All errors since the start of the app, newest last.
+#[derive(Debug)]
+pub struct ErrorLog {
+    start: Instant,
+    entries: VecDeque<LoggedError>,
+    /// Number of the newest entries scrolled past in the log view.
+    pub scroll: usize,
+}
+
+impl Default for ErrorLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ErrorLog {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            entries: VecDeque::new(),
+            scroll: 0,
+        }
+    }
+
+    /// Logs `message`, which came from the mitch named `device` if given.
+    pub fn push(&mut self, device: Option<String>, message: impl fmt::Display) {
+        self.log(device, message, false);
+    }
+
+    /// Logs `message` like [`ErrorLog::push`], without showing it as toast.
+    pub fn push_quiet(&mut self, device: Option<String>, message: impl fmt::Display) {
+        self.log(device, message, true);
+    }
+
+    fn log(&mut self, device: Option<String>, message: impl fmt::Display, quiet: bool) {
+        if self.entries.len() == CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(LoggedError {
+            time: Instant::now(),
+            device,
+            message: message.to_string(),
+            quiet,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.scroll = min(self.scroll + 1, self.len().saturating_sub(1));
+    }
+
+    /// The errors young enough to be shown as toasts, oldest first.
+    fn toasts(&self) -> impl Iterator<Item = &LoggedError> {
+        let mut young: Vec<&LoggedError> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|e| !e.quiet)
+            .take(MAX_TOASTS)
+            .take_while(|e| e.time.elapsed() < TOAST_TIME)
+            .collect();
+        young.reverse();
+        young.into_iter()
+    }
+
+    /// Renders the current toasts stacked in the upper right corner of `area`.
+    pub fn render_toasts(&self, area: Rect, buf: &mut Buffer) {
+        let width = min(60, area.width / 2);
+        let height = 4;
+        let mut y = area.y + 1;
+        for error in self.toasts() {
+            if y + height > area.bottom() {
+                break;
+            }
+            let toast = Rect {
+                x: area.right().saturating_sub(width + 1),
+                y,
+                width,
+                height,
+            };
+            Clear.render(toast, buf);
+            Paragraph::new(error.message.as_str())
+                .wrap(Wrap { trim: true })
+                .style(Style::default().fg(Color::White))
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Rounded)
+                        .border_style(Style::default().fg(Color::Red))
+                        .title(error.source()),
+                )
+                .render(toast, buf);
+            y += height;
+        }
+    }
+}
+
+/// The log view, newest error first.
+impl WidgetRef for ErrorLog {
+    fn render_ref(&self, area: Rect, buf: &mut Buffer) {
+        let block = Block::bordered()
+            .title(format!("errors ({})", self.len()))
+            .title_alignment(Alignment::Center)
+            .title_bottom("`Up`/`Down` scroll, `Esc` back")
+            .border_style(Style::new().white())
+            .border_type(BorderType::Rounded);
+        let text = if self.is_empty() {
+            "No errors so far.".to_string()
+        } else {
+            self.entries
+                .iter()
+                .rev()
+                .skip(self.scroll)
+                .map(|e| {
+                    format!(
+                        "[{:>8.1}s] {}: {}",
+                        e.time.duration_since(self.start).as_secs_f64(),
+                        e.source(),
+                        e.message
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        Paragraph::new(text)
+            .style(Style::default().fg(Color::White))
+            .wrap(Wrap { trim: false })
+            .block(block)
+            .render(area, buf);
+    }
+}
@@ -2,15 +2,18 @@ use color_eyre::eyre::OptionExt;
 use futures::{FutureExt, StreamExt};
 use ratatui::crossterm::event::Event as CrosstermEvent;
 use std::time::Duration;
-use tokio::sync::mpsc;
+use tokio::{sync::mpsc, task::JoinHandle};
 
-use crate::bluetooth::{BluetoothEvent, BtleDiscoverTask};
+use crate::bluetooth::{
+    BluetoothEvent, BtleDiscoverTask, adapter::AdapterChoice, registry::Registry,
+    sim::SimDiscoverTask,
+};
 
 /// The frequency at which tick events are emitted.
-const TICK_FPS: f64 = 1.0;
+const TICK_FPS: f64 = 4.0;
 
 /// Representation of all possible events.
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum Event {
     /// An event that is emitted on a regular schedule.
     ///
@@ -41,6 +44,48 @@ pub enum AppEvent {
     Disconnect,
     StopRecord,
     StartRecord,
+    /// Start logging to the memory of the active mitch.
+    StartLog,
+    /// Stop logging on the active mitch.
+    StopLog,
+    /// Query the log files stored on the active mitch.
+    ListFiles,
+    PrevFile,
+    NextFile,
+    /// Download the selected log file of the active mitch.
+    Readout,
+    /// Erase the memory of the active mitch once all its files are downloaded.
+    EraseMemory,
+    /// Switch between leaving out and NaN filling lost packets on the active mitch.
+    ToggleGapFill,
+    /// Switch recording to CSV next to the LSL outlet on or off on the active mitch.
+    ToggleCsv,
+    /// Set the clock of the active mitch and estimate its offset again.
+    SyncClock,
+    /// Install the firmware given on the command line on the active mitch.
+    FirmwareUpdate,
+    /// Select the next stream mode of the active mitch.
+    NextMode,
+    /// Select the next sampling frequency of the active mitch.
+    NextFrequency,
+    /// Scan with the chosen adapters from now on.
+    SelectAdapter(AdapterChoice),
+    /// Select the active mitch for group start and stop, or unselect it.
+    ToggleSelect,
+    /// Start recording on all selected mitches at once.
+    GroupStart,
+    /// Stop recording on all selected mitches.
+    GroupStop,
+    /// Pair the two selected mitches into a subject, or dissolve the subject of the active one.
+    Pair,
+    /// Swap left and right in the subject of the active mitch.
+    SwapSides,
+    /// Switch the combined stream of the subject of the active mitch on or off.
+    ToggleCombined,
+    /// Keep the settings of the active mitch and its partner in the device registry.
+    Remember,
+    /// Start or stop recording all streams to an XDF file.
+    ToggleXdf,
 }
 
 /// Terminal event handler.
@@ -50,23 +95,64 @@ pub struct EventHandler {
     sender: mpsc::UnboundedSender<Event>,
     /// Event receiver channel.
     receiver: mpsc::UnboundedReceiver<Event>,
+    /// Number of simulated mitches, `None` when scanning for Bluetooth devices.
+    simulate: Option<usize>,
+    /// Known devices, handed to every discovery.
+    registry: Registry,
+    /// The running discovery task.
+    discovery: JoinHandle<()>,
 }
 
 impl Default for EventHandler {
     fn default() -> Self {
-        Self::new()
+        Self::new(AdapterChoice::default(), Registry::default())
+    }
+}
+
+impl Drop for EventHandler {
+    fn drop(&mut self) {
+        self.discovery.abort();
     }
 }
 
 impl EventHandler {
-    /// Constructs a new instance of [`EventHandler`] and spawns a new thread to handle events.
-    pub fn new() -> Self {
+    /// Constructs a new instance of [`EventHandler`] and spawns a new thread to handle events,
+    /// scanning with the chosen `adapter` and setting up known devices as `registry` says.
+    pub fn new(adapter: AdapterChoice, registry: Registry) -> Self {
+        Self::spawn(None, adapter, registry)
+    }
+
+    /// Constructs a new instance of [`EventHandler`] that discovers `count` simulated mitches
+    /// instead of scanning for Bluetooth devices, with a registry that is not kept.
+    pub fn simulated(count: usize) -> Self {
+        Self::spawn(Some(count), AdapterChoice::default(), Registry::default())
+    }
+
+    fn spawn(simulate: Option<usize>, adapter: AdapterChoice, registry: Registry) -> Self {
         let (sender, receiver) = mpsc::unbounded_channel();
         let event_actor = EventTask::new(sender.clone());
-        let btle_actor = BtleDiscoverTask::new(sender.clone());
         tokio::spawn(async { event_actor.run().await });
-        tokio::spawn(async { btle_actor.run().await });
-        Self { sender, receiver }
+        let discovery = spawn_discovery(sender.clone(), simulate, adapter, registry.clone());
+        Self {
+            sender,
+            receiver,
+            simulate,
+            registry,
+            discovery,
+        }
+    }
+
+    /// Restarts the discovery with the chosen `adapter`.
+    ///
+    /// Mitches found before stay reachable through the adapter they were found with.
+    pub fn select_adapter(&mut self, adapter: AdapterChoice) {
+        self.discovery.abort();
+        self.discovery = spawn_discovery(
+            self.sender.clone(),
+            self.simulate,
+            adapter,
+            self.registry.clone(),
+        );
     }
 
     /// Receives an event from the sender.
@@ -85,6 +171,16 @@ impl EventHandler {
             .ok_or_eyre("Failed to receive event")
     }
 
+    /// The registry the discovery sets up known devices with.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// A sender for tasks that report events on their own, like the mitch tasks.
+    pub fn sender(&self) -> mpsc::UnboundedSender<Event> {
+        self.sender.clone()
+    }
+
     /// Queue an app event to be sent to the event receiver.
     ///
     /// This is useful for sending events to the event handler which will be processed by the next
@@ -96,6 +192,28 @@ impl EventHandler {
     }
 }
 
+/// Spawns the discovery of `simulate` simulated mitches, or of real ones with `adapter`.
+fn spawn_discovery(
+    sender: mpsc::UnboundedSender<Event>,
+    simulate: Option<usize>,
+    adapter: AdapterChoice,
+    registry: Registry,
+) -> JoinHandle<()> {
+    match simulate {
+        Some(count) => {
+            let task = SimDiscoverTask::new(sender, count, adapter, registry);
+            tokio::spawn(async {
+                // Announcing a simulated mitch does not fail
+                let _ = task.run().await;
+            })
+        }
+        None => {
+            let task = BtleDiscoverTask::new(sender, adapter, registry);
+            tokio::spawn(async { task.run().await })
+        }
+    }
+}
+
 /// A thread that handles reading crossterm events and emitting tick events on a regular schedule.
 struct EventTask {
     /// Event sender channel.
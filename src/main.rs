@@ -1,15 +1,108 @@
-use crate::app::App;
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use color_eyre::eyre::{OptionExt, eyre};
+
+use crate::{
+    app::App,
+    bluetooth::{adapter::AdapterChoice, firmware::FirmwareImage, registry::Registry},
+};
 
 pub mod app;
 pub mod bluetooth;
+pub mod errors;
 pub mod event;
+pub mod markers;
 pub mod ui;
+pub mod update;
+pub mod xdf;
 
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
+    let args = Args::parse()?;
+    let firmware = match &args.firmware {
+        Some(path) => Some(Arc::new(FirmwareImage::load(path)?)),
+        None => None,
+    };
+    // Simulated mitches are not kept in the registry of real ones
+    let registry = match args.simulate {
+        Some(_) => Registry::default(),
+        None => Registry::load()?,
+    };
+    if args.update {
+        let image = firmware.ok_or_eyre("--update needs a firmware file, see --firmware")?;
+        return update::run(
+            image,
+            args.allow_downgrade,
+            args.simulate,
+            args.adapter,
+            registry,
+        )
+        .await;
+    }
+    let mut app = match args.simulate {
+        Some(count) => App::simulated(count),
+        None => App::new(args.adapter, registry),
+    };
+    app.firmware = firmware;
+    app.allow_downgrade = args.allow_downgrade;
+    if let Some(stale_after) = args.stale_after {
+        app.mitches.stale_after = stale_after;
+    }
     let terminal = ratatui::init();
-    let result = App::new().run(terminal).await;
+    let result = app.run(terminal).await;
     ratatui::restore();
     result
 }
+
+/// Command line arguments.
+#[derive(Debug, Default)]
+struct Args {
+    /// Number of simulated mitches to use instead of scanning for Bluetooth devices.
+    simulate: Option<usize>,
+    /// Firmware file to install on the devices.
+    firmware: Option<PathBuf>,
+    /// Update all devices in range to `firmware` without starting the TUI.
+    update: bool,
+    /// Install `firmware` on devices running a newer version too.
+    allow_downgrade: bool,
+    /// Time without advertisements after which a mitch is marked stale.
+    stale_after: Option<Duration>,
+    /// Bluetooth adapters to scan with, all of them by default.
+    adapter: AdapterChoice,
+}
+
+impl Args {
+    fn parse() -> color_eyre::Result<Self> {
+        let mut parsed = Self::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--simulate" => {
+                    let count = args
+                        .next()
+                        .ok_or_eyre("--simulate expects a device count")?;
+                    parsed.simulate = Some(count.parse()?);
+                }
+                "--firmware" => {
+                    let path = args.next().ok_or_eyre("--firmware expects a file")?;
+                    parsed.firmware = Some(path.into());
+                }
+                "--update" => parsed.update = true,
+                "--allow-downgrade" => parsed.allow_downgrade = true,
+                "--adapter" => {
+                    let adapter = args
+                        .next()
+                        .ok_or_eyre("--adapter expects a name, an index or `all`")?;
+                    parsed.adapter = adapter.parse()?;
+                }
+                "--stale" => {
+                    let seconds = args.next().ok_or_eyre("--stale expects seconds")?;
+                    parsed.stale_after = Some(Duration::try_from_secs_f64(seconds.parse()?)?);
+                }
+                _ => return Err(eyre!("Unknown argument: {arg}")),
+            }
+        }
+        Ok(parsed)
+    }
+}
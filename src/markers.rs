@@ -0,0 +1,45 @@
+//! The LSL marker stream of the app, telling consumers what happened during a session.
+
+use lsl::{ChannelFormat, ExPushable, StreamInfo};
+
+use crate::{
+    bluetooth::outlet::SendOutlet,
+    xdf::{XdfRecorder, XdfStream},
+};
+
+/// Name and type of the marker stream announced to LSL consumers.
+pub const MARKER_STREAM: &str = "mitchrs-markers";
+pub const MARKER_TYPE: &str = "Markers";
+
+/// Outlet of the marker stream, one string per marker.
+pub struct MarkerOutlet(SendOutlet, XdfStream);
+
+impl MarkerOutlet {
+    /// Opens the marker stream, which is also recorded by `xdf` while it records.
+    pub fn new(xdf: &XdfRecorder) -> color_eyre::Result<Self> {
+        let info = StreamInfo::new(
+            MARKER_STREAM,
+            MARKER_TYPE,
+            1,
+            0.0,
+            ChannelFormat::String,
+            MARKER_STREAM,
+        )?;
+        let outlet = SendOutlet::new(&info)?;
+        Ok(Self(outlet, xdf.stream(info.to_xml()?)))
+    }
+
+    /// Pushes `marker` stamped with the LSL time `time`.
+    pub fn push(&self, marker: &str, time: f64) -> color_eyre::Result<()> {
+        self.0
+            .push_sample_ex(&vec![marker.to_string()], time, true)?;
+        self.1.push_strings(&[marker.to_string()], &[time]);
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for MarkerOutlet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("MarkerOutlet").field(&MARKER_STREAM).finish()
+    }
+}
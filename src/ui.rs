@@ -1,12 +1,20 @@
+use std::cmp::max;
+
 use ratatui::{
     buffer::Buffer,
-    layout::{Alignment, Rect},
+    layout::{Alignment, Constraint, Flex, Layout, Rect},
     style::{Color, Style, Stylize},
-    text::Span,
-    widgets::{Block, BorderType, Paragraph, Widget, WidgetRef as _},
+    text::{Line, Text},
+    widgets::{Block, BorderType, Clear, Paragraph, Widget, WidgetRef as _},
 };
 
-use crate::app::{App, AppState};
+use crate::{
+    app::{App, AppState},
+    bluetooth::{
+        adapter::{self, AdapterState},
+        subject::Side,
+    },
+};
 
 impl Widget for &App {
     /// Renders the user interface widgets.
@@ -23,27 +31,116 @@ impl Widget for &App {
             AppState::Mitch => {
                 self.render_mitch(area, buf);
             }
+            AppState::Errors => {
+                // The log shows the errors already, toasts would only hide it
+                self.errors.render_ref(area, buf);
+                return;
+            }
+            AppState::Adapters => {
+                self.render_adapters(area, buf);
+            }
         }
+        self.errors.render_toasts(area, buf);
     }
 }
 
 impl App {
     fn render_menu(&self, area: Rect, buf: &mut Buffer) {
+        let scanning: Vec<String> = self
+            .adapters
+            .iter()
+            .flatten()
+            .filter(|a| a.state == AdapterState::Scanning)
+            .map(|a| {
+                let connections = self.mitches.load.connections(&a.name);
+                format!("{} ({connections} connected)", a.name)
+            })
+            .collect();
+        let mut title = if scanning.is_empty() {
+            "mitchrs".to_string()
+        } else {
+            format!("mitchrs - scanning with {}", scanning.join(", "))
+        };
+        if let Some(path) = self.mitches.xdf.path() {
+            title += &format!(" - recording to {}", path.display());
+        }
         let block = Block::bordered()
-            .title("mitchrs")
+            .title(title)
             .title_alignment(Alignment::Center)
             .border_style(Style::new().white())
             .border_type(BorderType::Rounded);
 
         let text = "This is a tui template.\n\
-                Press `Esc`, `Ctrl-C` or `q` to stop running.\n\
+                Press `Esc`, `Ctrl-C` or `q` to stop running, `e` to show the error log, \
+                `a` to choose the adapters.\n\
+                `Space` selects a mitch, `r`/`s` start/stop recording on all selected at once.\n\
+                `p` pairs the two selected into a subject or unpairs, `w` swaps sides, \
+                `o` toggles the combined L/R stream.\n\
+                `k` keeps settings, subject and sides of the active mitch in the device registry.\n\
+                `X` starts/stops recording all streams and markers to one XDF file.\n\
             ";
 
-        let paragraph = Span::styled(text, Style::new().bg(Color::Black));
+        let paragraph = Text::styled(text, Style::new().bg(Color::Black));
 
         let p = Paragraph::new(paragraph).block(block);
         p.render(area, buf);
         self.mitches.render_ref(area, buf);
+
+        // Mitches found before stay usable, so the list is only covered in the middle
+        if let Some(message) = adapter::message(self.adapters.as_deref()) {
+            let width = message.lines().map(|l| l.len()).max().unwrap_or(0) + 4;
+            let height = message.lines().count() + 2;
+            let a = center(
+                area,
+                Constraint::Length(width as u16),
+                Constraint::Length(height as u16),
+            );
+            Clear.render(a, buf);
+            Paragraph::new(message)
+                .style(Style::default().fg(Color::Yellow))
+                .centered()
+                .block(Block::bordered().border_type(BorderType::Rounded))
+                .render(a, buf);
+        }
+    }
+
+    fn render_adapters(&self, area: Rect, buf: &mut Buffer) {
+        let block = Block::bordered()
+            .title("adapters")
+            .title_alignment(Alignment::Center)
+            .title_bottom("`Up`/`Down` select, `Enter` scan with it, `Esc` back")
+            .border_style(Style::new().white())
+            .border_type(BorderType::Rounded);
+        block.render(area, buf);
+
+        let mut entries = vec!["all adapters".to_string()];
+        entries.extend(self.adapters.iter().flatten().enumerate().map(|(i, a)| {
+            format!(
+                "{i}: {} - {}, {} connected",
+                a.name,
+                a.state,
+                self.mitches.load.connections(&a.name)
+            )
+        }));
+        let lines: Vec<Line> = entries
+            .into_iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let color = if i == self.selected_adapter {
+                    Color::Cyan
+                } else {
+                    Color::White
+                };
+                Line::styled(text, Style::default().fg(color))
+            })
+            .collect();
+        let width = lines.iter().map(|l| l.width()).max().unwrap_or(0);
+        let a = center(
+            area,
+            Constraint::Length(max(width, 20) as u16),
+            Constraint::Length(lines.len() as u16),
+        );
+        Paragraph::new(lines).render(a, buf);
     }
 
     fn render_mitch(&self, area: Rect, buf: &mut Buffer) {
@@ -52,12 +149,43 @@ impl App {
             .title_alignment(Alignment::Center)
             .border_style(Style::new().white())
             .border_type(BorderType::Rounded);
-        let text = "Press `Esc`, `Ctrl-C` or `q` to stop running.\n";
+        let text = "Press `Esc`, `Ctrl-C` or `q` to stop running.\n\
+                `c` connect, `d` disconnect, `r` record, `s` stop, \
+                `l` start logging, `L` stop logging, `m` stream mode, `f` frequency\n\
+                `v` list log files, `Up`/`Down` select file, `D` download, `E` erase memory\n\
+                `g` fill gaps with NaN, `x` record to CSV, `t` sync clock, `U` update firmware, \
+                `e` error log\n\
+            ";
 
-        let paragraph = Span::styled(text, Style::new().bg(Color::Black));
+        let [detail, quality] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(6)]).areas(block.inner(area));
+        let paragraph = Text::styled(text, Style::new().bg(Color::Black));
         let p = Paragraph::new(paragraph).block(block);
         p.render(area, buf);
 
-        self.mitches.get_active().render_ref(area, buf);
+        // A pair is shown side by side, left insole first
+        let mut snapshots: Vec<_> = self
+            .mitches
+            .active_with_partner()
+            .iter()
+            .map(|m| m.snapshot())
+            .collect();
+        snapshots.sort_by_key(|s| s.side != Some(Side::Left));
+        let columns = vec![Constraint::Fill(1); snapshots.len()];
+        let details = Layout::horizontal(columns.clone()).split(detail);
+        let qualities = Layout::horizontal(columns).split(quality);
+        for (i, snapshot) in snapshots.iter().enumerate() {
+            snapshot.render_ref(details[i], buf);
+            snapshot.quality.render_ref(qualities[i], buf);
+        }
     }
 }
+
+/// The part of `area` of the given size in its middle.
+pub(crate) fn center(area: Rect, horizontal: Constraint, vertical: Constraint) -> Rect {
+    let [area] = Layout::horizontal([horizontal])
+        .flex(Flex::SpaceAround)
+        .areas(area);
+    let [area] = Layout::vertical([vertical]).flex(Flex::Center).areas(area);
+    area
+}
@@ -0,0 +1,157 @@
+//! Headless firmware update of every mitch in range.
+
+use std::{sync::Arc, time::Duration};
+
+use color_eyre::eyre::eyre;
+use tokio::{select, sync::mpsc};
+
+use crate::{
+    bluetooth::{
+        BluetoothEvent, BtleDiscoverTask,
+        actor::{MitchCommand, MitchHandle, MitchSnapshot},
+        adapter::{self, AdapterChoice, AdapterLoad},
+        firmware::{FirmwareImage, FirmwareStatus},
+        registry::Registry,
+        sim::SimDiscoverTask,
+    },
+    event::Event,
+};
+
+/// How long to scan for devices before the updates start.
+const SCAN_TIME: Duration = Duration::from_secs(10);
+/// Interval at which the progress of the updates is printed.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Installs `image` on all mitches found while scanning with `adapter`, or on `simulate`
+/// simulated ones. Known devices are named as `registry` says. Devices running newer firmware
+/// are only downgraded if `allow_downgrade`.
+///
+/// Progress is printed to stdout, one line per device and step.
+pub async fn run(
+    image: Arc<FirmwareImage>,
+    allow_downgrade: bool,
+    simulate: Option<usize>,
+    adapter: AdapterChoice,
+    registry: Registry,
+) -> color_eyre::Result<()> {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    match simulate {
+        Some(count) => {
+            let task = SimDiscoverTask::new(sender, count, adapter, registry);
+            tokio::spawn(async { task.run().await });
+        }
+        None => {
+            let task = BtleDiscoverTask::new(sender, adapter, registry);
+            tokio::spawn(async { task.run().await });
+        }
+    }
+
+    println!(
+        "Scanning {}s for mitches to update to {}",
+        SCAN_TIME.as_secs(),
+        image.version
+    );
+    // Failures show up in the snapshots, the error events of the devices are not needed
+    let (device_events, _) = mpsc::unbounded_channel();
+    let mut mitches: Vec<MitchHandle> = Vec::new();
+    // Spreads the updates over all adapters in range
+    let load = AdapterLoad::default();
+    let mut message = None;
+    let scan = tokio::time::sleep(SCAN_TIME);
+    tokio::pin!(scan);
+    loop {
+        select! {
+            _ = &mut scan => break,
+            event = receiver.recv() => match event {
+                Some(Event::Bluetooth(BluetoothEvent::Discovered(mut mitch))) => {
+                    println!("{}: found", mitch.name());
+                    mitch.share_load(load.clone());
+                    mitches.push(MitchHandle::spawn(*mitch, device_events.clone()));
+                }
+                Some(Event::Bluetooth(BluetoothEvent::Reachable(transport))) => {
+                    if let Some(mitch) = mitches.iter().find(|m| m.id() == transport.id()) {
+                        mitch.send(MitchCommand::AddRoute(transport));
+                    }
+                }
+                Some(Event::Bluetooth(BluetoothEvent::Adapters(adapters))) => {
+                    let current = adapter::message(Some(&adapters));
+                    if let Some(text) = current.as_ref().filter(|_| current != message) {
+                        println!("{}", text.replace('\n', " "));
+                    }
+                    message = current;
+                }
+                Some(Event::Bluetooth(BluetoothEvent::Error { error, .. })) => {
+                    println!("{error}");
+                }
+                Some(_) => {}
+                // The discovery ended, nothing more will show up
+                None => break,
+            },
+        }
+    }
+    if mitches.is_empty() {
+        return Err(eyre!("No mitch found"));
+    }
+
+    for mitch in &mitches {
+        mitch.send(MitchCommand::Connect);
+        mitch.send(MitchCommand::UpdateFirmware {
+            image: image.clone(),
+            allow_downgrade,
+        });
+    }
+
+    let mut printed = vec![String::new(); mitches.len()];
+    let failed = loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let snapshots: Vec<MitchSnapshot> = mitches.iter().map(|m| m.snapshot()).collect();
+        for (snapshot, printed) in snapshots.iter().zip(&mut printed) {
+            let line = match (&snapshot.firmware, &snapshot.last_error) {
+                (Some(status), _) => describe(status),
+                (None, Some(e)) => format!("failed: {e}"),
+                (None, None) => continue,
+            };
+            if line != *printed {
+                println!("{}: {line}", snapshot.name);
+                *printed = line;
+            }
+        }
+        if snapshots.iter().all(is_finished) {
+            break snapshots.iter().filter(|s| !is_done(s)).count();
+        }
+    };
+
+    if failed > 0 {
+        return Err(eyre!("{failed} of {} updates failed", mitches.len()));
+    }
+    println!("All {} mitches updated to {}", mitches.len(), image.version);
+    Ok(())
+}
+
+/// Whether the update of a device succeeded or failed.
+fn is_finished(snapshot: &MitchSnapshot) -> bool {
+    match &snapshot.firmware {
+        Some(status) => status.is_finished(),
+        // Connecting or starting the update failed
+        None => snapshot.last_error.is_some(),
+    }
+}
+
+fn is_done(snapshot: &MitchSnapshot) -> bool {
+    matches!(snapshot.firmware, Some(FirmwareStatus::Done(_)))
+}
+
+/// One progress line, the transfer advancing in steps of ten percent.
+fn describe(status: &FirmwareStatus) -> String {
+    match status {
+        FirmwareStatus::Checking => "checking".to_string(),
+        FirmwareStatus::EnteringBootloader => "entering bootloader".to_string(),
+        FirmwareStatus::Transferring { sent, size } => {
+            format!("transferring {}%", sent * 10 / size.max(&1) * 10)
+        }
+        FirmwareStatus::Verifying => "verifying".to_string(),
+        FirmwareStatus::Rebooting => "rebooting".to_string(),
+        FirmwareStatus::Done(version) => format!("done, running {version}"),
+        FirmwareStatus::Failed(e) => format!("failed: {e}"),
+    }
+}
@@ -0,0 +1,436 @@
+//! Recording of every stream of the app to a single XDF file, without an external recorder.
+//!
+//! An XDF file is the magic `XDF:` followed by chunks of `[length][tag][content]`, where the
+//! length is a variable length integer counting tag and content. The file starts with a
+//! FileHeader, each stream is introduced by a StreamHeader holding the XML of its LSL
+//! [`StreamInfo`](lsl::StreamInfo) and its samples follow in Samples chunks. Once the recording
+//! stops, every stream gets a StreamFooter with its first and last timestamp and sample count.
+//!
+//! The samples are stamped on the LSL clock of this host already, so the ClockOffset chunks
+//! written at the start and end of each stream always hold an offset of zero. They are there for
+//! readers that expect them.
+//!
+//! A stream joins the recording with its next samples, whether it started before or after the
+//! recording did.
+//!
+//! The chunks are encoded by the streams and written to the file by a thread of its own, so no
+//! stream waits on the disk or on another stream that does.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, mpsc},
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::eyre::eyre;
+
+const MAGIC: &[u8] = b"XDF:";
+const FILE_HEADER: u16 = 1;
+const STREAM_HEADER: u16 = 2;
+const SAMPLES: u16 = 3;
+const CLOCK_OFFSET: u16 = 4;
+const STREAM_FOOTER: u16 = 6;
+
+/// The XDF recording shared by the app and every stream, recording while a file is open.
+#[derive(Clone, Debug, Default)]
+pub struct XdfRecorder(Arc<Mutex<RecorderState>>);
+
+#[derive(Debug, Default)]
+struct RecorderState {
+    file: Option<XdfFile>,
+    /// Number of files opened so far, tells a stream whether it joined the open one.
+    files: u64,
+    /// Why the last recording ended early.
+    error: Option<String>,
+}
+
+#[derive(Debug)]
+struct XdfFile {
+    path: PathBuf,
+    /// Encoded chunks on their way to the writer.
+    chunks: mpsc::Sender<Vec<u8>>,
+    /// Writes the chunks until the sender is dropped, ending early only if writing fails.
+    writer: JoinHandle<io::Result<()>>,
+    /// The streams of the file, the stream id is the index plus one.
+    streams: Vec<StreamRecord>,
+}
+
+/// What the footer of a stream tells.
+#[derive(Debug, Default)]
+struct StreamRecord {
+    first_timestamp: Option<f64>,
+    last_timestamp: Option<f64>,
+    sample_count: u64,
+    /// Collection time and value of the clock offsets written.
+    offsets: Vec<(f64, f64)>,
+}
+
+impl XdfRecorder {
+    /// Opens a new file in `dir` named after the current time and records to it from now on.
+    pub fn start(&self, dir: &Path) -> color_eyre::Result<PathBuf> {
+        let mut state = self.0.lock().unwrap();
+        if let Some(file) = &state.file {
+            return Err(eyre!("Recording to {} already", file.path.display()));
+        }
+        fs::create_dir_all(dir)?;
+        let started = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let path = dir.join(format!("mitchrs_{started}.xdf"));
+        let out = BufWriter::new(File::create_new(&path)?);
+        let (chunks, received) = mpsc::channel::<Vec<u8>>();
+        let writer = thread::spawn(move || {
+            let mut out = out;
+            for chunk in received {
+                out.write_all(&chunk)?;
+            }
+            out.flush()
+        });
+        let _ = chunks.send(MAGIC.to_vec());
+        let file = XdfFile {
+            path: path.clone(),
+            chunks,
+            writer,
+            streams: Vec::new(),
+        };
+        let header = r#"<?xml version="1.0"?><info><version>1.0</version></info>"#;
+        file.send(FILE_HEADER, header.as_bytes());
+        state.file = Some(file);
+        state.files += 1;
+        state.error = None;
+        Ok(path)
+    }
+
+    /// Ends the recording, writing the footers of all its streams.
+    ///
+    /// Fails if the file could not be completed, or if the recording had ended early already.
+    pub fn stop(&self) -> color_eyre::Result<()> {
+        let mut file = {
+            let mut state = self.0.lock().unwrap();
+            if let Some(error) = state.error.take() {
+                return Err(eyre!(error));
+            }
+            let Some(file) = state.file.take() else {
+                return Ok(());
+            };
+            file
+        };
+        file.finish();
+        let path = file.path.clone();
+        file.close()
+            .map_err(|e| eyre!("Writing {} failed: {e}", path.display()))
+    }
+
+    /// The file being recorded to.
+    pub fn path(&self) -> Option<PathBuf> {
+        let state = self.0.lock().unwrap();
+        state.file.as_ref().map(|f| f.path.clone())
+    }
+
+    /// Why the recording ended early, reported once.
+    pub fn take_error(&self) -> Option<String> {
+        self.0.lock().unwrap().error.take()
+    }
+
+    /// A stream described by the XML `header` that is recorded while a file is open.
+    pub fn stream(&self, header: String) -> XdfStream {
+        XdfStream {
+            recorder: self.clone(),
+            header,
+            id: Mutex::new(None),
+        }
+    }
+
+    /// Hands a chunk of the stream `stream` encoded by `write` to the writer, giving up the
+    /// recording if writing failed.
+    fn write(&self, stream: &XdfStream, write: impl FnOnce(&mut XdfFile, u32)) {
+        let mut guard = self.0.lock().unwrap();
+        let state = &mut *guard;
+        let Some(file) = &mut state.file else {
+            return;
+        };
+        let mut joined = stream.id.lock().unwrap();
+        let id = match *joined {
+            Some((files, id)) if files == state.files => id,
+            _ => {
+                let id = file.add_stream(&stream.header);
+                *joined = Some((state.files, id));
+                id
+            }
+        };
+        write(file, id);
+        // The writer is done already, and the thread gone, only if writing failed
+        if file.writer.is_finished() {
+            let path = file.path.display().to_string();
+            if let Some(Err(e)) = state.file.take().map(XdfFile::close) {
+                state.error = Some(format!("Writing {path} failed: {e}"));
+            }
+        }
+    }
+}
+
+/// A stream recorded by an [`XdfRecorder`].
+#[derive(Debug)]
+pub struct XdfStream {
+    recorder: XdfRecorder,
+    /// The XML of the StreamHeader.
+    header: String,
+    /// The file the stream joined, counted by the recorder, and its id in it.
+    id: Mutex<Option<(u64, u32)>>,
+}
+
+impl XdfStream {
+    /// Records numeric `samples` with their timestamps `stamps`.
+    pub fn push(&self, samples: &[Vec<f64>], stamps: &[f64]) {
+        self.recorder.write(self, |file, id| {
+            let mut values = Vec::new();
+            for sample in samples {
+                values.extend(sample.iter().flat_map(|v| v.to_le_bytes()));
+            }
+            let width = samples.first().map_or(0, Vec::len) * 8;
+            file.samples(id, stamps, |i| &values[i * width..(i + 1) * width])
+        });
+    }
+
+    /// Records string `samples` of a single channel with their timestamps `stamps`.
+    pub fn push_strings(&self, samples: &[String], stamps: &[f64]) {
+        self.recorder.write(self, |file, id| {
+            let values: Vec<Vec<u8>> = samples
+                .iter()
+                .map(|s| {
+                    let mut value = varlen(s.len() as u64);
+                    value.extend_from_slice(s.as_bytes());
+                    value
+                })
+                .collect();
+            file.samples(id, stamps, |i| &values[i])
+        });
+    }
+}
+
+impl XdfFile {
+    /// Writes the header of a new stream with the XML `header` and returns its id.
+    fn add_stream(&mut self, header: &str) -> u32 {
+        self.streams.push(StreamRecord::default());
+        let id = self.streams.len() as u32;
+        let mut content = id.to_le_bytes().to_vec();
+        content.extend_from_slice(header.as_bytes());
+        self.send(STREAM_HEADER, &content);
+        self.clock_offset(id);
+        id
+    }
+
+    /// Writes a Samples chunk of the stream `id` with `stamps.len()` samples, whose encoded
+    /// values `value` gives by index.
+    fn samples<'a>(&mut self, id: u32, stamps: &[f64], value: impl Fn(usize) -> &'a [u8]) {
+        if stamps.is_empty() {
+            return;
+        }
+        let mut content = id.to_le_bytes().to_vec();
+        content.extend(varlen(stamps.len() as u64));
+        for (i, stamp) in stamps.iter().enumerate() {
+            content.push(8);
+            content.extend_from_slice(&stamp.to_le_bytes());
+            content.extend_from_slice(value(i));
+        }
+        self.send(SAMPLES, &content);
+        let record = &mut self.streams[id as usize - 1];
+        record.first_timestamp = record.first_timestamp.or(stamps.first().copied());
+        record.last_timestamp = stamps.last().copied();
+        record.sample_count += stamps.len() as u64;
+    }
+
+    /// Writes a ClockOffset chunk of the stream `id`, taken now.
+    fn clock_offset(&mut self, id: u32) {
+        let time = lsl::local_clock();
+        let offset = 0.0;
+        let mut content = id.to_le_bytes().to_vec();
+        content.extend_from_slice(&time.to_le_bytes());
+        content.extend_from_slice(&f64::to_le_bytes(offset));
+        self.send(CLOCK_OFFSET, &content);
+        self.streams[id as usize - 1].offsets.push((time, offset));
+    }
+
+    /// Writes the last clock offsets and the footers of all streams.
+    fn finish(&mut self) {
+        for id in 1..=self.streams.len() as u32 {
+            self.clock_offset(id);
+            let footer = self.streams[id as usize - 1].footer();
+            let mut content = id.to_le_bytes().to_vec();
+            content.extend_from_slice(footer.as_bytes());
+            self.send(STREAM_FOOTER, &content);
+        }
+    }
+
+    /// Hands a chunk with `tag` and `content` to the writer.
+    fn send(&self, tag: u16, content: &[u8]) {
+        // Only fails if writing failed, which closing the file reports
+        let _ = self.chunks.send(chunk(tag, content));
+    }
+
+    /// Waits for the writer to write every chunk sent and flush the file.
+    fn close(self) -> io::Result<()> {
+        drop(self.chunks);
+        self.writer
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("the writer panicked")))
+    }
+}
+
+impl StreamRecord {
+    /// The XML of the StreamFooter.
+    fn footer(&self) -> String {
+        let mut xml = r#"<?xml version="1.0"?><info>"#.to_string();
+        xml += &format!(
+            "<first_timestamp>{}</first_timestamp><last_timestamp>{}</last_timestamp>",
+            self.first_timestamp.unwrap_or(0.0),
+            self.last_timestamp.unwrap_or(0.0),
+        );
+        xml += &format!("<sample_count>{}</sample_count>", self.sample_count);
+        xml += "<clock_offsets>";
+        for (time, value) in &self.offsets {
+            xml += &format!("<offset><time>{time}</time><value>{value}</value></offset>");
+        }
+        xml + "</clock_offsets></info>"
+    }
+}
+
+/// Encodes a chunk with `tag` and `content`.
+fn chunk(tag: u16, content: &[u8]) -> Vec<u8> {
+    let mut chunk = varlen(content.len() as u64 + 2);
+    chunk.extend_from_slice(&tag.to_le_bytes());
+    chunk.extend_from_slice(content);
+    chunk
+}
+
+/// Encodes `value` as variable length integer: the number of bytes that follow, then the value
+/// in as few of 1, 4 or 8 little endian bytes as it fits.
+fn varlen(value: u64) -> Vec<u8> {
+    if let Ok(value) = u8::try_from(value) {
+        vec![1, value]
+    } else if let Ok(value) = u32::try_from(value) {
+        let mut bytes = vec![4];
+        bytes.extend_from_slice(&value.to_le_bytes());
+        bytes
+    } else {
+        let mut bytes = vec![8];
+        bytes.extend_from_slice(&value.to_le_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chunk read back from a file.
+    struct Chunk<'a> {
+        /// Bytes the chunk length was encoded in.
+        width: u8,
+        tag: u16,
+        content: &'a [u8],
+    }
+
+    /// Reads a variable length integer off the front of `bytes`.
+    fn read_varlen(bytes: &mut &[u8]) -> u64 {
+        let width = bytes[0] as usize;
+        assert!([1, 4, 8].contains(&width), "width {width}");
+        let mut value = [0; 8];
+        value[..width].copy_from_slice(&bytes[1..=width]);
+        *bytes = &bytes[width + 1..];
+        u64::from_le_bytes(value)
+    }
+
+    /// Splits the chunks following the magic, each has to end where the next starts.
+    fn chunks(mut bytes: &[u8]) -> Vec<Chunk<'_>> {
+        let mut chunks = Vec::new();
+        while !bytes.is_empty() {
+            let width = bytes[0];
+            let len = read_varlen(&mut bytes) as usize;
+            let (chunk, rest) = bytes.split_at(len);
+            chunks.push(Chunk {
+                width,
+                tag: u16::from_le_bytes([chunk[0], chunk[1]]),
+                content: &chunk[2..],
+            });
+            bytes = rest;
+        }
+        chunks
+    }
+
+    #[test]
+    fn varlen_picks_smallest_width() {
+        assert_eq!(varlen(0), [1, 0]);
+        assert_eq!(varlen(255), [1, 255]);
+        assert_eq!(varlen(256), [4, 0, 1, 0, 0]);
+        assert_eq!(varlen(u32::MAX as u64), [4, 255, 255, 255, 255]);
+        assert_eq!(varlen(u32::MAX as u64 + 1), [8, 0, 0, 0, 0, 1, 0, 0, 0]);
+        for value in [255, 256, u32::MAX as u64, u32::MAX as u64 + 1] {
+            assert_eq!(read_varlen(&mut &varlen(value)[..]), value);
+        }
+    }
+
+    #[test]
+    fn writes_streams_with_footers() {
+        let dir = std::env::temp_dir().join(format!("mitchrs-xdf-{}", std::process::id()));
+        let recorder = XdfRecorder::default();
+        let path = recorder.start(&dir).unwrap();
+        let numbers = recorder.stream("<info>numbers</info>".to_string());
+        numbers.push(&[vec![1.0, 2.0], vec![3.0, 4.0]], &[10.0, 10.5]);
+        numbers.push(&[vec![5.0, 6.0]], &[11.0]);
+        // Long enough for the chunk length to take four bytes
+        let marker = "m".repeat(300);
+        let markers = recorder.stream("<info>markers</info>".to_string());
+        markers.push_strings(std::slice::from_ref(&marker), &[10.2]);
+        recorder.stop().unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(&bytes[..4], MAGIC);
+        let chunks = chunks(&bytes[4..]);
+        let tags: Vec<u16> = chunks.iter().map(|c| c.tag).collect();
+        assert_eq!(
+            tags,
+            [
+                FILE_HEADER,
+                STREAM_HEADER,
+                CLOCK_OFFSET,
+                SAMPLES,
+                SAMPLES,
+                STREAM_HEADER,
+                CLOCK_OFFSET,
+                SAMPLES,
+                CLOCK_OFFSET,
+                STREAM_FOOTER,
+                CLOCK_OFFSET,
+                STREAM_FOOTER,
+            ]
+        );
+        assert_eq!(&chunks[1].content[..4], 1_u32.to_le_bytes());
+        assert_eq!(&chunks[1].content[4..], b"<info>numbers</info>");
+        assert_eq!(&chunks[5].content[..4], 2_u32.to_le_bytes());
+
+        // Stream id, sample count, then per sample the stamp and the values
+        let mut samples = &chunks[3].content[4..];
+        assert_eq!(chunks[3].width, 1);
+        assert_eq!(read_varlen(&mut samples), 2);
+        assert_eq!(samples[0], 8);
+        assert_eq!(samples[1..9], 10.0_f64.to_le_bytes());
+        assert_eq!(samples[9..17], 1.0_f64.to_le_bytes());
+        assert_eq!(samples.len(), 2 * (1 + 8 + 2 * 8));
+
+        let mut strings = &chunks[7].content[4..];
+        assert_eq!(chunks[7].width, 4);
+        assert_eq!(read_varlen(&mut strings), 1);
+        strings = &strings[9..];
+        assert_eq!(read_varlen(&mut strings), 300);
+        assert_eq!(strings, marker.as_bytes());
+
+        let footer = |chunk: &Chunk| String::from_utf8(chunk.content[4..].to_vec()).unwrap();
+        assert!(footer(&chunks[9]).contains("<sample_count>3</sample_count>"));
+        assert!(footer(&chunks[9]).contains("<first_timestamp>10</first_timestamp>"));
+        assert!(footer(&chunks[9]).contains("<last_timestamp>11</last_timestamp>"));
+        assert!(footer(&chunks[11]).contains("<sample_count>1</sample_count>"));
+    }
+}